@@ -0,0 +1,231 @@
+//! The SASL-style mechanism negotiation driven by the server's `/auth/mechanisms` and
+//! `/auth/step` endpoints. [`AuthSessions`] holds the in-progress exchanges; [`System`]
+//! itself stays the source of truth for credentials and failed-attempt accounting.
+
+use std::collections::HashMap;
+
+use rand::RngCore;
+
+use crate::{protocol::Mechanism, users::UserId, System};
+
+/// Every mechanism the server is willing to negotiate, in the order `/auth/mechanisms`
+/// advertises them.
+pub const MECHANISMS: &[Mechanism] = &[Mechanism::Plain, Mechanism::Login, Mechanism::Cram];
+
+const NONCE_LEN: usize = 32;
+const SESSION_ID_LEN: usize = 16;
+
+/// The next thing an in-progress exchange is waiting on.
+enum Pending {
+    LoginAwaitingUsername,
+    LoginAwaitingPassword { username: String },
+    CramAwaitingResponse { nonce: Vec<u8> },
+}
+
+/// What the caller (the `/auth/step` handler) should do with a [`AuthSessions`] result:
+/// relay another challenge, or the exchange finished and `uid` is authenticated.
+pub enum Step {
+    Challenge { session: String, data: Vec<u8> },
+    Done(UserId),
+}
+
+/// Per-connection in-progress auth exchanges, keyed by a server-issued session id. Unlike
+/// `System`, this is never persisted: a half-finished login is meaningless across a restart.
+#[derive(Default)]
+pub struct AuthSessions {
+    pending: HashMap<String, Pending>,
+}
+
+impl AuthSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn issue_session(&mut self, pending: Pending) -> String {
+        let mut bytes = [0u8; SESSION_ID_LEN];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let session = hex::encode(bytes);
+        self.pending.insert(session.clone(), pending);
+        session
+    }
+
+    /// Begins a new exchange for `mechanism`. `data` is the client's initial response, used
+    /// only by [`Mechanism::Plain`]; the other mechanisms speak first, so it must be empty.
+    pub fn start(&mut self, sys: &mut System, mechanism: Mechanism, data: &[u8]) -> anyhow::Result<Step> {
+        match mechanism {
+            Mechanism::Plain => {
+                let (username, password) = parse_plain(data)?;
+                let uid = sys.login(&username, &password)?;
+                Ok(Step::Done(uid))
+            }
+            Mechanism::Login => {
+                if !data.is_empty() {
+                    anyhow::bail!("LOGIN has no initial response");
+                }
+                let session = self.issue_session(Pending::LoginAwaitingUsername);
+                Ok(Step::Challenge {
+                    session,
+                    data: b"Username:".to_vec(),
+                })
+            }
+            Mechanism::Cram => {
+                if !data.is_empty() {
+                    anyhow::bail!("CRAM has no initial response");
+                }
+                let mut nonce = vec![0u8; NONCE_LEN];
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let session = self.issue_session(Pending::CramAwaitingResponse { nonce: nonce.clone() });
+                Ok(Step::Challenge { session, data: nonce })
+            }
+        }
+    }
+
+    /// Feeds the client's reply for an already-open `session` forward.
+    pub fn step(&mut self, sys: &mut System, session: &str, data: &[u8]) -> anyhow::Result<Step> {
+        let pending = self
+            .pending
+            .remove(session)
+            .ok_or_else(|| anyhow::anyhow!("unknown or expired auth session"))?;
+
+        match pending {
+            Pending::LoginAwaitingUsername => {
+                let username = String::from_utf8(data.to_vec())?;
+                let session = self.issue_session(Pending::LoginAwaitingPassword { username });
+                Ok(Step::Challenge {
+                    session,
+                    data: b"Password:".to_vec(),
+                })
+            }
+            Pending::LoginAwaitingPassword { username } => {
+                let password = String::from_utf8(data.to_vec())?;
+                let uid = sys.login(&username, &password)?;
+                Ok(Step::Done(uid))
+            }
+            Pending::CramAwaitingResponse { nonce } => {
+                let reply = std::str::from_utf8(data)?;
+                let (username, response) = reply
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow::anyhow!("expected \"username response\""))?;
+                let response = hex::decode(response)?;
+                let (uid, _verifier) = sys.cram_lookup(username)?;
+                let uid = sys.verify_cram(uid, &nonce, &response)?;
+                Ok(Step::Done(uid))
+            }
+        }
+    }
+}
+
+/// Splits a SASL PLAIN blob (`\0username\0password`, the authzid before the first NUL is
+/// unused here) into its username and password.
+fn parse_plain(data: &[u8]) -> anyhow::Result<(String, String)> {
+    let text = std::str::from_utf8(data)?;
+    let mut parts = text.split('\0');
+    let _authzid = parts.next();
+    let username = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("malformed PLAIN response"))?;
+    let password = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("malformed PLAIN response"))?;
+    Ok((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::*;
+    use crate::{users, System};
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    /// `UserDb::new` seeds an `admin` account with an empty password; every test here logs
+    /// in as that account instead of provisioning a fresh user.
+    fn system() -> System {
+        System::new().unwrap()
+    }
+
+    #[test]
+    fn plain_logs_in_in_one_round() {
+        let mut sys = system();
+        let mut sessions = AuthSessions::new();
+
+        let step = sessions
+            .start(&mut sys, Mechanism::Plain, b"\0admin\0")
+            .unwrap();
+        assert!(matches!(step, Step::Done(_)));
+    }
+
+    #[test]
+    fn login_mechanism_takes_two_rounds() {
+        let mut sys = system();
+        let mut sessions = AuthSessions::new();
+
+        let step = sessions.start(&mut sys, Mechanism::Login, b"").unwrap();
+        let session = match step {
+            Step::Challenge { session, data } => {
+                assert_eq!(data, b"Username:");
+                session
+            }
+            Step::Done(_) => panic!("LOGIN shouldn't finish before a username is given"),
+        };
+
+        let step = sessions.step(&mut sys, &session, b"admin").unwrap();
+        let session = match step {
+            Step::Challenge { session, data } => {
+                assert_eq!(data, b"Password:");
+                session
+            }
+            Step::Done(_) => panic!("LOGIN shouldn't finish before a password is given"),
+        };
+
+        let step = sessions.step(&mut sys, &session, b"").unwrap();
+        assert!(matches!(step, Step::Done(_)));
+    }
+
+    #[test]
+    fn cram_round_trip_never_sends_the_password() {
+        let mut sys = system();
+        let mut sessions = AuthSessions::new();
+
+        let step = sessions.start(&mut sys, Mechanism::Cram, b"").unwrap();
+        let (session, nonce) = match step {
+            Step::Challenge { session, data } => (session, data),
+            Step::Done(_) => panic!("CRAM shouldn't finish before a nonce is answered"),
+        };
+
+        let verifier = users::cram_verifier("admin", "").unwrap();
+        let mut mac = HmacSha256::new_from_slice(&verifier).unwrap();
+        mac.update(&nonce);
+        let response = hex::encode(mac.finalize().into_bytes());
+        let reply = format!("admin {}", response);
+
+        let step = sessions.step(&mut sys, &session, reply.as_bytes()).unwrap();
+        assert!(matches!(step, Step::Done(_)));
+    }
+
+    #[test]
+    fn cram_rejects_wrong_response() {
+        let mut sys = system();
+        let mut sessions = AuthSessions::new();
+
+        let step = sessions.start(&mut sys, Mechanism::Cram, b"").unwrap();
+        let session = match step {
+            Step::Challenge { session, .. } => session,
+            Step::Done(_) => panic!("CRAM shouldn't finish before a nonce is answered"),
+        };
+
+        sessions
+            .step(&mut sys, &session, b"admin deadbeef")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn step_rejects_unknown_session() {
+        let mut sys = system();
+        let mut sessions = AuthSessions::new();
+
+        sessions.step(&mut sys, "not-a-real-session", b"").unwrap_err();
+    }
+}
@@ -1,17 +1,34 @@
 use std::{
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use filesys::{
     fs::NodeTag,
-    protocol::{self, EDeserialize, ResResult},
-    users::{Perms, Username},
+    log::LogLevel,
+    protocol::{AuthStepRequest, AuthStepResponse, EDeserialize, Mechanism, ResResult},
+    users::{self, Perms, UserId, Username},
     Action, ActionRes,
 };
+use hmac::{Hmac, Mac};
 use reqwest::{Client, ClientBuilder};
+use rocket::time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use sha2::Sha256;
 use structopt::{clap::AppSettings, StructOpt};
 
+type HmacSha256 = Hmac<Sha256>;
+
+/// Max bytes sent per `/upload` request; well under the server's own chunk-size backstop.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+fn parse_since(s: &str) -> anyhow::Result<OffsetDateTime> {
+    OffsetDateTime::parse(s, &Rfc3339).map_err(Into::into)
+}
+
+fn parse_uid(s: &str) -> anyhow::Result<UserId> {
+    Ok(UserId::new(s.parse()?))
+}
+
 #[derive(Debug, structopt::StructOpt)]
 struct Opt {
     #[structopt(long, default_value = "127.0.0.1")]
@@ -50,52 +67,144 @@ enum Cmd {
     Exec { path: PathBuf },
     SetPerms { path: PathBuf, perms: Perms },
     Ls { path: PathBuf },
+    /// Streams a local file to the server in fixed-size chunks instead of buffering it
+    /// whole into one JSON request; `path` must not already exist.
+    Put { local: PathBuf, path: PathBuf },
+    /// Streams a server file to a local path, the inverse of `put`.
+    Get { path: PathBuf, local: PathBuf },
     AddUser { username: Username },
     ChangePass,
+    Logs {
+        #[structopt(long)]
+        level: Option<LogLevel>,
+        #[structopt(long, parse(try_from_str = parse_since))]
+        since: Option<OffsetDateTime>,
+        #[structopt(long, parse(try_from_str = parse_uid))]
+        user: Option<UserId>,
+        #[structopt(long)]
+        limit: Option<usize>,
+    },
     Exit,
 }
 
 pub struct State {
     base: String,
     client: Client,
+    token: String,
 }
 
 fn print_action_res(res: &ActionRes) {
     match res {
         ActionRes::Ok => {}
         ActionRes::Read(str) => println!("{}", str),
+        ActionRes::ReadBytes(bytes) => println!("<{} bytes>", bytes.len()),
         ActionRes::Ls(entries) => {
             for entry in entries {
                 let tag = match entry.tag {
                     NodeTag::File => 'f',
                     NodeTag::Dir => 'd',
+                    NodeTag::Symlink => 'l',
                 };
-                println!("{}{} {:>4} {}", tag, entry.perms, entry.size, entry.name);
+                print!("{}{} {:>4} {}", tag, entry.perms, entry.size, entry.name);
+                if let Some(target) = &entry.target {
+                    print!(" -> {}", target);
+                }
+                println!();
+            }
+        }
+        ActionRes::Logs(logs) => {
+            for log in logs {
+                println!("{}", log);
             }
         }
     }
 }
 
+/// Logs in over the CRAM mechanism so the password itself never crosses the wire: fetch
+/// the nonce `/auth/step` hands out, answer with `HMAC-SHA256(cram_verifier, nonce)` where
+/// `cram_verifier` is the same `Argon2(password, salt = SHA-256(username))` the server
+/// derives (see [`users::cram_verifier`]), and return the session token the server issues
+/// once that checks out.
+async fn cram_login(
+    client: &Client,
+    base: &str,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<String> {
+    let mechanisms: Vec<Mechanism> = client
+        .get(format!("{}/auth/mechanisms", base))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if !mechanisms.contains(&Mechanism::Cram) {
+        anyhow::bail!("server doesn't support CRAM authentication");
+    }
+
+    let start = AuthStepRequest {
+        session: None,
+        mechanism: Some(Mechanism::Cram),
+        data: String::new(),
+    };
+    let step = auth_step(client, base, &start).await?;
+    let (session, nonce) = match step {
+        AuthStepResponse::Challenge { session, data } => (session, base64::decode(data)?),
+        AuthStepResponse::Done { .. } => {
+            anyhow::bail!("server finished CRAM authentication without issuing a challenge")
+        }
+    };
+
+    let verifier = users::cram_verifier(username, password)?;
+    let mut mac =
+        HmacSha256::new_from_slice(&verifier).expect("HMAC accepts a key of any length");
+    mac.update(&nonce);
+    let response = hex::encode(mac.finalize().into_bytes());
+
+    let reply = AuthStepRequest {
+        session: Some(session),
+        mechanism: None,
+        data: base64::encode(format!("{} {}", username, response)),
+    };
+    match auth_step(client, base, &reply).await? {
+        AuthStepResponse::Done { token } => Ok(token),
+        AuthStepResponse::Challenge { .. } => {
+            anyhow::bail!("server asked for another CRAM round than expected")
+        }
+    }
+}
+
+async fn auth_step(
+    client: &Client,
+    base: &str,
+    req: &AuthStepRequest,
+) -> anyhow::Result<AuthStepResponse> {
+    client
+        .post(format!("{}/auth/step", base))
+        .json(req)
+        .send()
+        .await?
+        .json::<ResResult<AuthStepResponse>>()
+        .await?
+        .deserialize()
+}
+
 impl State {
     async fn new(base: String, username: String, password: String) -> anyhow::Result<Self> {
         let client = ClientBuilder::new().cookie_store(true).build()?;
 
-        let creds = protocol::LoginInfo { username, password };
-
-        client
-            .post(format!("{}/login", base))
-            .json(&creds)
-            .send()
-            .await?
-            .json::<ResResult<()>>()
-            .await?
-            .deserialize()?;
+        let token = cram_login(&client, &base, &username, &password).await?;
 
-        Ok(Self { base, client })
+        Ok(Self {
+            base,
+            client,
+            token,
+        })
     }
 
     async fn execute(&self, cmd: Cmd) -> anyhow::Result<bool> {
         let cmd = match cmd {
+            Cmd::Put { local, path } => return self.put(&local, &path).await.map(|_| false),
+            Cmd::Get { path, local } => return self.get(&path, &local).await.map(|_| false),
             Cmd::Read { path } => Action::Read(path),
             Cmd::Write { path, data } => Action::Write(path, data),
             Cmd::Rm { path } => Action::Rm(path),
@@ -114,12 +223,24 @@ impl State {
                 }
                 Action::ChangePassword { old, new }
             }
+            Cmd::Logs {
+                level,
+                since,
+                user,
+                limit,
+            } => Action::Logs {
+                level,
+                since,
+                uid: user,
+                limit,
+            },
             Cmd::Exit => return Ok(true),
         };
 
         let res = self
             .client
             .post(format!("{}/exec", self.base))
+            .bearer_auth(&self.token)
             .json(&cmd)
             .send()
             .await?
@@ -130,6 +251,56 @@ impl State {
         print_action_res(&res);
         Ok(false)
     }
+
+    /// Streams `local` to the server at `path` as a sequence of `/upload` requests, each
+    /// carrying one chunk's raw bytes plus its offset, so the whole file never has to sit
+    /// in memory as one JSON-encoded request.
+    async fn put(&self, local: &Path, path: &Path) -> anyhow::Result<()> {
+        let data = std::fs::read(local)?;
+
+        self.client
+            .post(format!("{}/exec", self.base))
+            .bearer_auth(&self.token)
+            .json(&Action::NewFile(path.to_owned()))
+            .send()
+            .await?
+            .json::<ResResult<ActionRes>>()
+            .await?
+            .deserialize()?;
+
+        let path = path.to_string_lossy().into_owned();
+        for (i, chunk) in data.chunks(UPLOAD_CHUNK_SIZE).enumerate() {
+            let offset = (i * UPLOAD_CHUNK_SIZE) as u64;
+            self.client
+                .post(format!("{}/upload", self.base))
+                .query(&[("path", path.as_str()), ("offset", &offset.to_string())])
+                .bearer_auth(&self.token)
+                .body(chunk.to_vec())
+                .send()
+                .await?
+                .json::<ResResult<()>>()
+                .await?
+                .deserialize()?;
+        }
+        Ok(())
+    }
+
+    /// Fetches `path` from the server's streaming `/download` endpoint and writes it to
+    /// `local`, the inverse of `put`.
+    async fn get(&self, path: &Path, local: &Path) -> anyhow::Result<()> {
+        let res = self
+            .client
+            .get(format!("{}/download", self.base))
+            .query(&[("path", path.to_string_lossy().as_ref())])
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            anyhow::bail!("{}", res.text().await?);
+        }
+        std::fs::write(local, res.bytes().await?)?;
+        Ok(())
+    }
 }
 
 #[tokio::main]
@@ -1,45 +1,92 @@
-use std::{io::ErrorKind, sync::Arc};
+use std::{io::ErrorKind, path::PathBuf, sync::Arc};
 
 use filesys::{
-    protocol::{ApiKey, IntoSerialize, LoginInfo, ResResult},
+    auth::{self, AuthSessions},
+    bootstrap,
+    protocol::{
+        ApiKey, AuthStepRequest, AuthStepResponse, IntoSerialize, LoginInfo, Mechanism, ResResult,
+    },
     users::UserId,
     Action, ActionRes, System, SystemImage,
 };
 use rocket::{
+    async_trait,
+    data::{Data, ToByteUnit},
     futures::lock::Mutex,
-    http::{Cookie, CookieJar},
-    post, routes,
+    get,
+    http::{Cookie, CookieJar, Status},
+    post,
+    request::{FromRequest, Outcome},
+    response::{status::Custom, stream::ByteStream},
+    routes,
     serde::json::Json,
     time::Duration,
-    State,
+    Request, State,
 };
 use structopt::StructOpt;
 
 type Sys = Arc<Mutex<System>>;
+type Sessions = Arc<Mutex<AuthSessions>>;
+
+/// Max size of one `/upload` chunk; the reqwest CLI sends much smaller chunks, this is
+/// just the server's backstop against a single request buffering too much memory.
+fn upload_chunk_limit() -> rocket::data::ByteUnit {
+    16.mebibytes()
+}
+
+/// Size of one piece yielded by `/download`'s `ByteStream`, so a large file is trickled
+/// out over several writes instead of handed to the client in one frame.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The bearer token from an `Authorization: Bearer <token>` header, if present. Lets
+/// `exec` accept a JWT directly, so headless clients (the reqwest CLI) don't need a
+/// cookie jar; browser clients can keep relying on the `apikey` cookie set at login.
+struct BearerToken(Option<String>);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(str::to_owned);
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+/// Mints a signed session JWT for `uid` and sets it as the `apikey` cookie, for any flow
+/// (cleartext login or a finished SASL exchange) that ends with an authenticated user.
+fn issue_token(uid: UserId, opt: &Opt, cookies: &CookieJar<'_>) -> anyhow::Result<String> {
+    let token = ApiKey::new(uid, opt.api_exp_sec).encode(opt.jwt_secret.as_bytes())?;
+    let expires = rocket::time::OffsetDateTime::now_utc() + Duration::new(opt.api_exp_sec, 0);
+
+    let cookie = Cookie::build("apikey", token.clone())
+        .expires(Some(expires))
+        .finish();
+    cookies.add_private(cookie);
+    Ok(token)
+}
 
 async fn login(
     sys: &mut System,
     opt: &Opt,
     creds: &LoginInfo,
     cookies: &CookieJar<'_>,
-) -> anyhow::Result<UserId> {
+) -> anyhow::Result<String> {
     let uid = sys.login(&creds.username, &creds.password)?;
-    let api_key = ApiKey::new(uid);
-    let api_key = serde_json::to_string(&api_key)?;
-    let expires = rocket::time::OffsetDateTime::now_utc() + Duration::new(opt.api_exp_sec, 0);
-
-    let cookie = Cookie::build("apikey", api_key)
-        .expires(Some(expires))
-        .finish();
-    cookies.add_private(cookie);
-    Ok(uid)
+    issue_token(uid, opt, cookies)
 }
 
-fn get_api_key(cookies: &CookieJar<'_>) -> anyhow::Result<ApiKey> {
-    cookies
-        .get_private("apikey")
-        .and_then(|cookie| serde_json::from_str(cookie.value()).ok())
-        .ok_or_else(|| anyhow::anyhow!("authentication required"))
+fn get_api_key(cookies: &CookieJar<'_>, bearer: &BearerToken, opt: &Opt) -> anyhow::Result<ApiKey> {
+    let token = bearer
+        .0
+        .clone()
+        .or_else(|| cookies.get_private("apikey").map(|cookie| cookie.value().to_owned()))
+        .ok_or_else(|| anyhow::anyhow!("authentication required"))?;
+    ApiKey::decode(&token, opt.jwt_secret.as_bytes())
 }
 
 #[post("/login", format = "json", data = "<creds>")]
@@ -48,48 +95,198 @@ async fn login_endpoint(
     opt: &State<Opt>,
     creds: Json<LoginInfo>,
     cookies: &CookieJar<'_>,
-) -> Json<ResResult<()>> {
+) -> Json<ResResult<String>> {
+    let mut sys = sys.lock().await;
+    let res = login(&mut sys, opt, &creds, cookies).await.into_serialize();
+    Json(res)
+}
+
+#[get("/auth/mechanisms")]
+fn mechanisms_endpoint() -> Json<&'static [Mechanism]> {
+    Json(auth::MECHANISMS)
+}
+
+async fn auth_step(
+    sys: &mut System,
+    sessions: &mut AuthSessions,
+    opt: &Opt,
+    req: &AuthStepRequest,
+    cookies: &CookieJar<'_>,
+) -> anyhow::Result<AuthStepResponse> {
+    let data = base64::decode(&req.data)?;
+
+    let step = match &req.session {
+        Some(session) => sessions.step(sys, session, &data)?,
+        None => {
+            let mechanism = req
+                .mechanism
+                .ok_or_else(|| anyhow::anyhow!("mechanism required to start an exchange"))?;
+            sessions.start(sys, mechanism, &data)?
+        }
+    };
+
+    match step {
+        auth::Step::Challenge { session, data } => Ok(AuthStepResponse::Challenge {
+            session,
+            data: base64::encode(data),
+        }),
+        auth::Step::Done(uid) => {
+            let token = issue_token(uid, opt, cookies)?;
+            Ok(AuthStepResponse::Done { token })
+        }
+    }
+}
+
+#[post("/auth/step", format = "json", data = "<req>")]
+async fn auth_step_endpoint(
+    sys: &State<Sys>,
+    opt: &State<Opt>,
+    sessions: &State<Sessions>,
+    cookies: &CookieJar<'_>,
+    req: Json<AuthStepRequest>,
+) -> Json<ResResult<AuthStepResponse>> {
     let mut sys = sys.lock().await;
-    let res = login(&mut sys, opt, &creds, cookies)
+    let mut sessions = sessions.lock().await;
+    let res = auth_step(&mut sys, &mut sessions, opt, &req, cookies)
         .await
-        .into_serialize()
-        .map(|_| ());
+        .into_serialize();
     Json(res)
 }
 
 async fn exec(
     sys: &mut System,
     cookies: &CookieJar<'_>,
+    bearer: &BearerToken,
+    opt: &Opt,
     action: &Action,
 ) -> anyhow::Result<ActionRes> {
-    let api_key = get_api_key(cookies)?;
+    let api_key = get_api_key(cookies, bearer, opt)?;
     sys.exec(api_key.uid(), action)
 }
 
 #[post("/exec", format = "json", data = "<action>")]
 async fn exec_endpoint(
     sys: &State<Sys>,
+    opt: &State<Opt>,
     cookies: &CookieJar<'_>,
+    bearer: BearerToken,
     action: Json<Action>,
 ) -> Json<ResResult<ActionRes>> {
     let mut sys = sys.lock().await;
-    let res = exec(&mut sys, cookies, &action).await.into_serialize();
+    let res = exec(&mut sys, cookies, &bearer, opt, &action)
+        .await
+        .into_serialize();
     Json(res)
 }
 
+fn bad_request(err: anyhow::Error) -> Custom<Json<String>> {
+    Custom(Status::BadRequest, Json(format!("{:#}", err)))
+}
+
+async fn upload(
+    sys: &mut System,
+    cookies: &CookieJar<'_>,
+    bearer: &BearerToken,
+    opt: &Opt,
+    path: &str,
+    offset: u64,
+    chunk: Data<'_>,
+) -> anyhow::Result<()> {
+    let api_key = get_api_key(cookies, bearer, opt)?;
+
+    let data = chunk.open(upload_chunk_limit()).into_bytes().await?;
+    if !data.is_complete() {
+        anyhow::bail!("chunk exceeds max size of {}", upload_chunk_limit());
+    }
+
+    let action = Action::AppendBytes(PathBuf::from(path), offset, data.into_inner());
+    sys.exec(api_key.uid(), &action)?;
+    Ok(())
+}
+
+/// Appends one `offset`+`data` fragment of a chunked upload to `path`. Unlike `/exec`,
+/// the body is the chunk's raw bytes rather than a JSON-encoded `Action`, so a large file
+/// doesn't have to be buffered as one oversized JSON string. Callers create `path` with
+/// `Action::NewFile` first, then append chunks starting at offset `0`.
+#[post("/upload?<path>&<offset>", data = "<chunk>")]
+async fn upload_endpoint(
+    sys: &State<Sys>,
+    opt: &State<Opt>,
+    cookies: &CookieJar<'_>,
+    bearer: BearerToken,
+    path: String,
+    offset: u64,
+    chunk: Data<'_>,
+) -> Result<(), Custom<Json<String>>> {
+    let mut sys = sys.lock().await;
+    upload(&mut sys, cookies, &bearer, opt, &path, offset, chunk)
+        .await
+        .map_err(bad_request)
+}
+
+async fn download(
+    sys: &mut System,
+    cookies: &CookieJar<'_>,
+    bearer: &BearerToken,
+    opt: &Opt,
+    path: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let api_key = get_api_key(cookies, bearer, opt)?;
+    match sys.exec(api_key.uid(), &Action::ReadBytes(PathBuf::from(path)))? {
+        ActionRes::ReadBytes(bytes) => Ok(bytes),
+        _ => unreachable!("Action::ReadBytes always yields ActionRes::ReadBytes"),
+    }
+}
+
+/// Streams `path`'s bytes back in `DOWNLOAD_CHUNK_SIZE` pieces instead of returning them as
+/// one JSON-encoded string, the download-side counterpart of `/upload`.
+#[get("/download?<path>")]
+async fn download_endpoint(
+    sys: &State<Sys>,
+    opt: &State<Opt>,
+    cookies: &CookieJar<'_>,
+    bearer: BearerToken,
+    path: String,
+) -> Result<ByteStream![Vec<u8>], Custom<Json<String>>> {
+    let mut sys = sys.lock().await;
+    let bytes = download(&mut sys, cookies, &bearer, opt, &path)
+        .await
+        .map_err(bad_request)?;
+
+    Ok(ByteStream! {
+        for chunk in bytes.chunks(DOWNLOAD_CHUNK_SIZE) {
+            yield chunk.to_vec();
+        }
+    })
+}
+
 #[derive(Debug, structopt::StructOpt)]
 struct Opt {
     image: String,
 
     #[structopt(long, default_value = "60")]
     api_exp_sec: i64,
+
+    /// HMAC secret used to sign and verify session JWTs.
+    #[structopt(long, env = "FILESYS_JWT_SECRET")]
+    jwt_secret: String,
+
+    /// Max number of log entries kept in the ring buffer before the oldest is evicted.
+    #[structopt(long, default_value = "1024")]
+    log_capacity: usize,
+
+    /// TOML file of initial roles/users/files/perms, applied once at boot. Safe to point at
+    /// an existing image: roles, users and files that already exist are left untouched, but
+    /// `[[perms]]` entries are re-applied over a node's current perms.
+    #[structopt(long)]
+    config: Option<PathBuf>,
 }
 
 #[rocket::main]
 async fn main() -> anyhow::Result<()> {
     let opt = Opt::from_args();
 
-    let sys = match std::fs::File::open(&opt.image) {
+    let mut sys = match std::fs::File::open(&opt.image) {
         Err(err) if err.kind() == ErrorKind::NotFound => System::new()?,
         Ok(file) => {
             let image: SystemImage = bincode::deserialize_from(file)?;
@@ -97,15 +294,33 @@ async fn main() -> anyhow::Result<()> {
         }
         Err(err) => return Err(err.into()),
     };
+    filesys::log::set_capacity(opt.log_capacity);
+
+    if let Some(path) = &opt.config {
+        let config = bootstrap::Config::load(path)?;
+        sys.bootstrap(&config)?;
+    }
 
     let out = std::fs::File::create(&opt.image)?;
 
     let sys = Sys::new(Mutex::new(sys));
+    let sessions = Sessions::new(Mutex::new(AuthSessions::new()));
 
     rocket::build()
         .manage(sys.clone())
+        .manage(sessions)
         .manage(opt)
-        .mount("/", routes![login_endpoint, exec_endpoint])
+        .mount(
+            "/",
+            routes![
+                login_endpoint,
+                mechanisms_endpoint,
+                auth_step_endpoint,
+                exec_endpoint,
+                upload_endpoint,
+                download_endpoint
+            ],
+        )
         .launch()
         .await?;
 
@@ -0,0 +1,186 @@
+//! Declarative startup provisioning for a fresh `System`, read from a TOML file (the
+//! server's `--config` flag), modeled on fabaccess's `roles.toml`/`machines.toml` split:
+//! `[[roles]]` define wildcard permission bundles, `[[users]]` create accounts and assign
+//! them roles, `[[files]]` seed the initial tree, and `[[perms]]` grant owner/group/other
+//! access on it. Applying the same config twice is a no-op the second time: roles, users
+//! and files that already exist are skipped instead of erroring, and `[[perms]]` entries
+//! are set again, overwriting whatever perms the node has by then.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    fs::{Fs, NonInteractivePrompter},
+    roles::RoleDb,
+    users::{GroupDb, Perms, UserDb, ADMIN_ID},
+};
+
+#[derive(Debug, Deserialize)]
+struct RoleEntry {
+    name: String,
+    #[serde(default)]
+    patterns: Vec<String>,
+    #[serde(default)]
+    parents: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserEntry {
+    name: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NodeKind {
+    Dir,
+    File,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileEntry {
+    path: String,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Deserialize)]
+struct PermEntry {
+    path: String,
+    /// An owner/group/other triple in `Perms`'s `"rwxc rwxc rwxc"` display format.
+    perms: String,
+}
+
+/// A parsed bootstrap file; see the module docs for its `[[...]]` sections.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    roles: Vec<RoleEntry>,
+    #[serde(default)]
+    users: Vec<UserEntry>,
+    #[serde(default)]
+    files: Vec<FileEntry>,
+    #[serde(default)]
+    perms: Vec<PermEntry>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("parsing {:?}", path))
+    }
+}
+
+/// Applies `config` against an already-constructed system, in role -> user -> file -> perm
+/// order so later sections can rely on earlier ones (a user's role must exist before it's
+/// assigned, a file must exist before its perms are set). Every node and permission is
+/// created under [`ADMIN_ID`], since bootstrapping runs before any session has logged in.
+pub fn apply(
+    config: &Config,
+    users: &mut UserDb,
+    groups: &GroupDb,
+    roles: &mut RoleDb,
+    fs: &mut Fs,
+) -> anyhow::Result<()> {
+    let p = &mut NonInteractivePrompter;
+
+    for role in &config.roles {
+        if roles.contains(&role.name) {
+            continue;
+        }
+        roles
+            .add_role(&role.name, role.patterns.clone(), role.parents.clone())
+            .with_context(|| format!("adding role {:?}", role.name))?;
+        crate::info!(ADMIN_ID => "bootstrap: added role {:?}", role.name);
+    }
+
+    for user in &config.users {
+        let uid = match users.id_of(&user.name) {
+            Ok(uid) => uid,
+            Err(_) => {
+                let uid = users
+                    .add_user(&user.name, &user.password)
+                    .with_context(|| format!("adding user {:?}", user.name))?;
+                crate::info!(ADMIN_ID => "bootstrap: added user {:?}", user.name);
+                uid
+            }
+        };
+        for role in &user.roles {
+            roles
+                .assign(uid, role)
+                .with_context(|| format!("assigning role {:?} to {:?}", role, user.name))?;
+        }
+    }
+
+    for file in &config.files {
+        let path = Path::new(&file.path);
+        if fs.exists(ADMIN_ID, groups, roles, path, p) {
+            continue;
+        }
+        match file.kind {
+            NodeKind::Dir => fs.new_dir(ADMIN_ID, groups, roles, path, p),
+            NodeKind::File => fs.new_file(ADMIN_ID, groups, roles, path, p),
+        }
+        .with_context(|| format!("creating {:?}", path))?;
+        crate::info!(ADMIN_ID => "bootstrap: created {:?}", path);
+    }
+
+    for perm in &config.perms {
+        let path = Path::new(&perm.path);
+        let perms: Perms = perm
+            .perms
+            .parse()
+            .with_context(|| format!("parsing perms for {:?}", path))?;
+        fs.set_perms(ADMIN_ID, groups, roles, path, perms, p)
+            .with_context(|| format!("setting perms on {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_twice_is_idempotent() {
+        let config: Config = toml::from_str(
+            r#"
+            [[roles]]
+            name = "reader"
+            patterns = ["fs.home.*"]
+
+            [[users]]
+            name = "alice"
+            password = "hunter2"
+            roles = ["reader"]
+
+            [[files]]
+            path = "/home"
+            kind = "dir"
+
+            [[files]]
+            path = "/home/f"
+            kind = "file"
+
+            [[perms]]
+            path = "/home/f"
+            perms = "rwxc rwxc rwxc"
+            "#,
+        )
+        .unwrap();
+
+        let mut users = UserDb::new().unwrap();
+        let groups = GroupDb::new();
+        let mut roles = RoleDb::new();
+        let mut fs = Fs::new();
+
+        apply(&config, &mut users, &groups, &mut roles, &mut fs).unwrap();
+        apply(&config, &mut users, &groups, &mut roles, &mut fs)
+            .expect("re-applying the same config should be a no-op, not an error");
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    fs::{Fs, NonInteractivePrompter},
+    roles::RoleDb,
+    users::{GroupDb, Mode, Perms, UserDb, ADMIN_ID},
+};
+
+/// One `key = value` entry read from a config file, in the order it was last set.
+#[derive(Debug, Clone)]
+struct Entry {
+    key: String,
+    value: String,
+}
+
+/// A config tree merged from one or more layered INI-style files, Mercurial's `hgrc` style:
+/// `[section]` headers group `key = value` entries, a leading-whitespace line continues the
+/// previous value, `#`/`;` start a comment, `%include <path>` recursively merges another file
+/// in place, and `%unset <key>` removes a previously set key in the current section.
+#[derive(Debug, Default)]
+pub struct Config {
+    sections: HashMap<String, Vec<Entry>>,
+}
+
+impl Config {
+    /// Parses `path` (and everything it `%include`s) into a merged [`Config`]. Later layers
+    /// (later lines, later includes) override earlier ones of the same section/key.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        let mut stack = Vec::new();
+        config.merge_file(path, &mut stack)?;
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("resolving {:?}", path))?;
+        if stack.contains(&canonical) {
+            bail!("config include cycle at {:?}", path);
+        }
+
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        stack.push(canonical);
+        let result = self.merge_str(&text, path, stack);
+        stack.pop();
+        result
+    }
+
+    fn merge_str(
+        &mut self,
+        text: &str,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<()> {
+        let mut section = String::new();
+        let mut current_key: Option<String> = None;
+
+        for (i, raw) in text.lines().enumerate() {
+            let lineno = i + 1;
+            let err = |msg: String| anyhow::anyhow!("{}:{}: {}", path.display(), lineno, msg);
+
+            if starts_continuation(raw) {
+                let key = current_key
+                    .as_ref()
+                    .ok_or_else(|| err("continuation line with no preceding key".into()))?;
+                let entry = self
+                    .sections
+                    .entry(section.clone())
+                    .or_default()
+                    .iter_mut()
+                    .rev()
+                    .find(|e| &e.key == key)
+                    .expect("current_key always names an entry just inserted");
+                entry.value.push(' ');
+                entry.value.push_str(raw.trim());
+                continue;
+            }
+
+            let line = raw.trim();
+            current_key = None;
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("%include") {
+                let include = rest.trim();
+                if include.is_empty() {
+                    return Err(err("%include with no path".into()));
+                }
+                let include_path = resolve_include(path, include);
+                self.merge_file(&include_path, stack)
+                    .map_err(|source| err(format!("including {:?}: {}", include_path, source)))?;
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("%unset") {
+                let key = rest.trim();
+                if key.is_empty() {
+                    return Err(err("%unset with no key".into()));
+                }
+                if let Some(entries) = self.sections.get_mut(&section) {
+                    entries.retain(|e| e.key != key);
+                }
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| err("malformed section header".into()))?;
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| err("expected `key = value`".into()))?;
+            let key = key.trim().to_string();
+            let value = value.trim().to_string();
+
+            let entries = self.sections.entry(section.clone()).or_default();
+            entries.retain(|e| e.key != key);
+            entries.push(Entry {
+                key: key.clone(),
+                value,
+            });
+            current_key = Some(key);
+        }
+        Ok(())
+    }
+
+    fn section(&self, name: &str) -> &[Entry] {
+        self.sections.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+fn starts_continuation(raw: &str) -> bool {
+    (raw.starts_with(' ') || raw.starts_with('\t')) && !raw.trim().is_empty()
+}
+
+fn resolve_include(parent: &Path, include: &str) -> PathBuf {
+    let include_path = Path::new(include);
+    if include_path.is_absolute() {
+        return include_path.to_path_buf();
+    }
+    parent
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(include_path)
+}
+
+/// Applies a merged [`Config`]'s `[users]`/`[files]`/`[perms]` sections, giving a
+/// reproducible bootstrap instead of the empty, admin-only filesystem `Fs::new` otherwise
+/// starts with. `[users]` entries are `name = password`, `[files]` entries are
+/// `path = dir|file`, and `[perms]` entries are `path = rwxc` applied identically to the
+/// node's owner, group, and other modes. Paths are created in the order they're declared, so
+/// a directory must be listed before anything inside it.
+pub fn apply(
+    config: &Config,
+    users: &mut UserDb,
+    groups: &GroupDb,
+    roles: &RoleDb,
+    fs: &mut Fs,
+) -> anyhow::Result<()> {
+    let p = &mut NonInteractivePrompter;
+
+    for entry in config.section("users") {
+        users
+            .add_user(&entry.key, &entry.value)
+            .with_context(|| format!("adding user {:?}", entry.key))?;
+    }
+
+    for entry in config.section("files") {
+        let path = Path::new(&entry.key);
+        match entry.value.as_str() {
+            "dir" => fs.new_dir(ADMIN_ID, groups, roles, path, p),
+            "file" => fs.new_file(ADMIN_ID, groups, roles, path, p),
+            other => bail!(
+                "{:?}: unknown file kind {:?}, expected dir|file",
+                path,
+                other
+            ),
+        }
+        .with_context(|| format!("creating {:?}", path))?;
+    }
+
+    for entry in config.section("perms") {
+        let path = Path::new(&entry.key);
+        let mode: Mode = entry
+            .value
+            .parse()
+            .with_context(|| format!("parsing perms for {:?}", path))?;
+        let perms = Perms {
+            owner: mode,
+            group: mode,
+            other: mode,
+        };
+        fs.set_perms(ADMIN_ID, groups, roles, path, perms, p)
+            .with_context(|| format!("setting perms on {:?}", path))?;
+    }
+
+    Ok(())
+}
@@ -1,15 +1,56 @@
 use std::{
-    collections::HashMap,
-    path::{Component, Path},
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File as StdFile, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 
-use crate::users::{AccessMap, Op, Perms, UserId};
+use crate::roles::RoleDb;
+use crate::users::{GroupDb, GroupId, Mode, Op, Perms, Policy, UserId, ADMIN_ID, ROOT_GID};
 
 const ROOT_ID: NodeId = NodeId(0);
 
+/// Hop limit for symlink resolution in `Fs::resolve_path_impl`, modeled on Linux's
+/// `MAXSYMLINKS`: enough for any legitimate chain, small enough to catch a cycle quickly.
+const MAX_SYMLINK_HOPS: u32 = 40;
+
+/// Which of a node's owner/group/other subjects a requesting [`UserId`] resolves to.
+#[derive(Debug, Clone, Copy)]
+enum Subject {
+    Owner,
+    Group,
+    Other,
+}
+
+/// Where `Node::check_if_allowed` defers to the caller instead of granting or denying
+/// outright, modeled on the interactive permission prompts of the Deno runtime.
+pub trait PermissionPrompter {
+    fn prompt(&mut self, uid: UserId, path: &Path, ops: &[Op]) -> PromptResponse;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptResponse {
+    AllowOnce,
+    AllowAlways,
+    DenyOnce,
+    DenyAlways,
+}
+
+/// The default prompter for non-interactive contexts (tests, headless servers): every
+/// `Prompt` policy resolves to a one-off denial instead of blocking on user input.
+#[derive(Debug, Default)]
+pub struct NonInteractivePrompter;
+
+impl PermissionPrompter for NonInteractivePrompter {
+    fn prompt(&mut self, _uid: UserId, _path: &Path, _ops: &[Op]) -> PromptResponse {
+        PromptResponse::DenyOnce
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct NodeId(u64);
 
@@ -23,17 +64,32 @@ impl NodeId {
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct File {
-    content: String,
+    content: Vec<u8>,
 }
 impl File {
-    fn read(&self) -> &str {
+    fn read(&self) -> &[u8] {
         &self.content
     }
 
-    fn write(&mut self, data: &str) {
+    fn write(&mut self, data: &[u8]) {
         self.content.clear();
-        self.content += data;
+        self.content.extend_from_slice(data);
+    }
+
+    /// Appends one fragment of a chunked upload. `offset` must equal the file's current
+    /// size, so out-of-order or duplicate chunks are rejected instead of corrupting it.
+    fn append(&mut self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        if offset != self.content.len() as u64 {
+            anyhow::bail!(
+                "out-of-order chunk: expected offset {}, got {}",
+                self.content.len(),
+                offset
+            );
+        }
+        self.content.extend_from_slice(data);
+        Ok(())
     }
+
     fn size(&self) -> usize {
         self.content.len()
     }
@@ -85,108 +141,229 @@ impl Dir {
 enum NodeKind {
     File(File),
     Dir(Dir),
+    Symlink(String),
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum NodeTag {
     File,
     Dir,
+    Symlink,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Node {
     id: NodeId,
     kind: NodeKind,
-    perms: AccessMap,
+    owner: UserId,
+    group: GroupId,
+    perms: Perms,
+    /// A dotted capability label (e.g. `fs.home.some.dir`). A caller whose role patterns
+    /// glob-match it is granted any op on this node, bypassing `perms` entirely.
+    label: Option<String>,
 }
 
 impl Node {
-    pub fn new_with_tag(id: NodeId, parent_id: NodeId, tag: NodeTag) -> Self {
+    pub fn new_with_tag(id: NodeId, parent_id: NodeId, owner: UserId, tag: NodeTag) -> Self {
         match tag {
-            NodeTag::File => Self::new_file(id),
-            NodeTag::Dir => Self::new_dir(id, parent_id),
+            NodeTag::File => Self::new_file(id, owner),
+            NodeTag::Dir => Self::new_dir(id, parent_id, owner),
+            // Symlinks carry a target string `create`'s callers don't have; they're built
+            // via `Node::new_symlink` through `Fs::create_symlink` instead.
+            NodeTag::Symlink => unreachable!("symlinks aren't created through new_with_tag"),
         }
     }
 
-    pub fn new(id: NodeId, kind: NodeKind) -> Self {
+    pub fn new(id: NodeId, kind: NodeKind, owner: UserId) -> Self {
         Self {
             id,
             kind,
+            owner,
+            group: ROOT_GID,
             perms: Default::default(),
+            label: None,
         }
     }
 
-    pub fn new_file(id: NodeId) -> Self {
+    pub fn new_file(id: NodeId, owner: UserId) -> Self {
         Self {
             id,
             kind: NodeKind::File(File::default()),
+            owner,
+            group: ROOT_GID,
             perms: Default::default(),
+            label: None,
         }
     }
 
-    pub fn new_dir(id: NodeId, parent_id: NodeId) -> Self {
+    pub fn new_dir(id: NodeId, parent_id: NodeId, owner: UserId) -> Self {
         Self {
             id,
             kind: NodeKind::Dir(Dir::new(id, parent_id)),
+            owner,
+            group: ROOT_GID,
             perms: Default::default(),
+            label: None,
+        }
+    }
+
+    pub fn new_symlink(id: NodeId, owner: UserId, target: String) -> Self {
+        Self {
+            id,
+            kind: NodeKind::Symlink(target),
+            owner,
+            group: ROOT_GID,
+            perms: Default::default(),
+            label: None,
         }
     }
 
     pub fn as_file(&self) -> anyhow::Result<&File> {
         match &self.kind {
             NodeKind::File(f) => Ok(f),
-            NodeKind::Dir(_) => anyhow::bail!("is not a regular file"),
+            NodeKind::Dir(_) | NodeKind::Symlink(_) => anyhow::bail!("is not a regular file"),
         }
     }
 
     pub fn as_dir(&self) -> anyhow::Result<&Dir> {
         match &self.kind {
-            NodeKind::File(_) => anyhow::bail!("is not a dir"),
             NodeKind::Dir(d) => Ok(d),
+            NodeKind::File(_) | NodeKind::Symlink(_) => anyhow::bail!("is not a dir"),
         }
     }
 
     pub fn as_file_mut(&mut self) -> anyhow::Result<&mut File> {
         match &mut self.kind {
             NodeKind::File(f) => Ok(f),
-            NodeKind::Dir(_) => anyhow::bail!("is not a regular file"),
+            NodeKind::Dir(_) | NodeKind::Symlink(_) => anyhow::bail!("is not a regular file"),
         }
     }
 
     pub fn as_dir_mut(&mut self) -> anyhow::Result<&mut Dir> {
         match &mut self.kind {
-            NodeKind::File(_) => anyhow::bail!("is not a dir"),
             NodeKind::Dir(d) => Ok(d),
+            NodeKind::File(_) | NodeKind::Symlink(_) => anyhow::bail!("is not a dir"),
+        }
+    }
+
+    /// Returns the link's stored target, unresolved, if this node is a symlink.
+    pub fn as_symlink(&self) -> anyhow::Result<&str> {
+        match &self.kind {
+            NodeKind::Symlink(target) => Ok(target),
+            NodeKind::File(_) | NodeKind::Dir(_) => anyhow::bail!("is not a symlink"),
         }
     }
 
-    pub fn check_if_allowed(&self, uid: UserId, ops: &[Op]) -> anyhow::Result<()> {
-        if self.perms.allows(uid, ops) {
-            Ok(())
+    /// Resolves which of owner/group/other `uid` falls under for this node.
+    fn subject_for(&self, uid: UserId, groups: &GroupDb) -> Subject {
+        if uid == self.owner {
+            Subject::Owner
+        } else if groups.is_member(self.group, uid) {
+            Subject::Group
         } else {
-            anyhow::bail!("permission denied")
+            Subject::Other
         }
     }
 
-    pub fn set_perm(&mut self, uid: UserId, perms: impl Into<Perms>) {
-        self.perms.set(uid, perms)
+    fn mode_for(&self, subject: Subject) -> &Mode {
+        match subject {
+            Subject::Owner => &self.perms.owner,
+            Subject::Group => &self.perms.group,
+            Subject::Other => &self.perms.other,
+        }
+    }
+
+    fn mode_for_mut(&mut self, subject: Subject) -> &mut Mode {
+        match subject {
+            Subject::Owner => &mut self.perms.owner,
+            Subject::Group => &mut self.perms.group,
+            Subject::Other => &mut self.perms.other,
+        }
+    }
+
+    /// Checks whether `uid` may perform `ops` on this node, consulting `prompter` whenever
+    /// the resolved policy is `Prompt`. `AllowAlways`/`DenyAlways` responses are persisted
+    /// back into the node's owner/group/other mode, so later checks skip the prompt.
+    pub fn check_if_allowed(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        ops: &[Op],
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        if uid == ADMIN_ID {
+            return Ok(());
+        }
+        if let Some(label) = &self.label {
+            if roles.grants(uid, label) {
+                return Ok(());
+            }
+        }
+
+        let subject = self.subject_for(uid, groups);
+        match self.mode_for(subject).resolve(ops) {
+            Policy::Granted => Ok(()),
+            Policy::Denied => anyhow::bail!("permission denied"),
+            Policy::Prompt => {
+                let response = prompter.prompt(uid, path, ops);
+                let allow = matches!(
+                    response,
+                    PromptResponse::AllowOnce | PromptResponse::AllowAlways
+                );
+                if matches!(
+                    response,
+                    PromptResponse::AllowAlways | PromptResponse::DenyAlways
+                ) {
+                    let policy = if allow {
+                        Policy::Granted
+                    } else {
+                        Policy::Denied
+                    };
+                    let mode = self.mode_for_mut(subject);
+                    for op in ops {
+                        mode.set_policy(op, policy);
+                    }
+                }
+                if allow {
+                    Ok(())
+                } else {
+                    anyhow::bail!("permission denied")
+                }
+            }
+        }
+    }
+
+    pub fn set_perms(&mut self, perms: Perms) {
+        self.perms = perms;
+    }
+
+    pub fn set_owner(&mut self, owner: UserId) {
+        self.owner = owner;
+    }
+
+    pub fn set_group(&mut self, group: GroupId) {
+        self.group = group;
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
     }
 
     fn tag(&self) -> NodeTag {
         match &self.kind {
             NodeKind::File(_) => NodeTag::File,
             NodeKind::Dir(_) => NodeTag::Dir,
+            NodeKind::Symlink(_) => NodeTag::Symlink,
         }
     }
 
-    fn perms_for(&self, uid: UserId) -> Perms {
-        self.perms.get(uid)
-    }
-
     fn size(&self) -> usize {
         match &self.kind {
             NodeKind::File(f) => f.size(),
             NodeKind::Dir(d) => d.len(),
+            NodeKind::Symlink(target) => target.len(),
         }
     }
 }
@@ -195,14 +372,99 @@ impl Node {
 pub struct NodeEntry {
     pub tag: NodeTag,
     pub name: String,
+    pub owner: UserId,
+    pub group: GroupId,
     pub perms: Perms,
+    pub label: Option<String>,
     pub size: usize,
+    /// The link's target, set only when `tag` is [`NodeTag::Symlink`].
+    pub target: Option<String>,
+}
+
+/// On-disk header for the append-only persistence format, modeled on Mercurial's
+/// dirstate-v2 docket: just enough metadata to find the root and decide when to compact.
+/// Every field is a fixed-width integer, so the header always occupies [`HEADER_LEN`] bytes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct FileHeader {
+    node_counter: NodeId,
+    root_offset: u64,
+    unreachable_bytes: u64,
+    total_bytes: u64,
+}
+
+const HEADER_LEN: u64 = 32;
+
+/// Bytes preceding a record's node payload: the node's id and a tombstone flag. Kept fixed
+/// and tiny so indexing a file never has to decode a node just to read its id.
+const RECORD_HEADER_LEN: u64 = 9;
+
+/// State kept only for a file-backed [`Fs`]; `None` for the plain in-memory trees `Fs::new`
+/// builds for the REPL and tests.
+struct Backing {
+    file: StdFile,
+    mmap: Mmap,
+    path: PathBuf,
+    header: FileHeader,
+    /// Offset of each node's most recently written record, built once at `open` time and
+    /// kept up to date by `flush`/`compact`.
+    offsets: HashMap<NodeId, u64>,
+}
+
+impl std::fmt::Debug for Backing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Backing")
+            .field("path", &self.path)
+            .field("header", &self.header)
+            .finish()
+    }
+}
+
+/// Appends `node` (or a tombstone if `None`) to `file` as a length-prefixed record and
+/// returns `(body_offset, record_len)`, where `body_offset` is where the node's id starts
+/// (just past the length prefix) and `record_len` is the total bytes written including it.
+fn append_record(
+    file: &mut StdFile,
+    id: NodeId,
+    node: Option<&Node>,
+) -> anyhow::Result<(u64, u64)> {
+    let is_tombstone = node.is_none();
+    let body = node
+        .map(bincode::serialize)
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + body.len());
+    record.extend_from_slice(&id.0.to_le_bytes());
+    record.push(is_tombstone as u8);
+    record.extend_from_slice(&body);
+
+    let offset = file.seek(SeekFrom::End(0))?;
+    file.write_all(&(record.len() as u32).to_le_bytes())?;
+    file.write_all(&record)?;
+
+    Ok((offset + 4, 4 + record.len() as u64))
+}
+
+fn write_header(file: &mut StdFile, header: &FileHeader) -> anyhow::Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let bytes = bincode::serialize(header)?;
+    debug_assert_eq!(bytes.len() as u64, HEADER_LEN);
+    file.write_all(&bytes)?;
+    Ok(())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Fs {
     nodes: HashMap<NodeId, Node>,
     node_counter: NodeId,
+    #[serde(skip)]
+    backing: Option<Backing>,
+    /// Nodes created/changed since the last flush; appended fresh on the next `save`.
+    #[serde(skip)]
+    dirty: HashSet<NodeId>,
+    /// Nodes removed since the last flush; written as tombstones on the next `save`.
+    #[serde(skip)]
+    removed: HashSet<NodeId>,
 }
 
 impl Default for Fs {
@@ -216,115 +478,371 @@ impl Fs {
         let mut node_counter = ROOT_ID;
         let root_id = node_counter.next();
         let mut nodes = HashMap::new();
-        let root = Node::new_dir(root_id, root_id);
+        let root = Node::new_dir(root_id, root_id, ADMIN_ID);
         nodes.insert(root_id, root);
         Self {
             nodes,
             node_counter,
+            backing: None,
+            dirty: HashSet::new(),
+            removed: HashSet::new(),
         }
     }
 
-    /// Transform path into immutable reference
-    /// Checks for permissions on every segment of the path
-    fn resolve_path<'a>(&'a self, uid: UserId, path: &Path) -> anyhow::Result<&'a Node> {
-        let root = self.get_node(uid, ROOT_ID)?;
-        let node = reduce_segments(path, root, |node, name| self.lookup(uid, node.id, name))?;
-        Ok(node)
+    /// Transform path into a mutable reference, transparently following symlinks
+    /// (including one in the final segment). Checks for permissions on every segment of
+    /// the path, prompting through `prompter` whenever a segment's policy is `Prompt`.
+    fn resolve_path<'a>(
+        &'a mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<&'a mut Node> {
+        self.resolve_path_impl(uid, groups, roles, path, prompter, true)
     }
 
-    /// Transform path into the mutable reference
-    /// Checks for permissions on every segment of the pat
-    fn resolve_path_mut<'a>(
+    /// `lstat`-style variant of `resolve_path`: symlinks are still followed for every
+    /// segment except the last, so a link at the end of `path` resolves to the link node
+    /// itself rather than its target. Used by `ls`/`exists` so they can act on the link.
+    fn resolve_path_nofollow<'a>(
         &'a mut self,
         uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
         path: &Path,
+        prompter: &mut dyn PermissionPrompter,
     ) -> anyhow::Result<&'a mut Node> {
-        let id = self.resolve_path(uid, path)?.id;
-        self.get_node_mut(uid, id)
+        self.resolve_path_impl(uid, groups, roles, path, prompter, false)
     }
 
-    /// Lookup Node in directory with id = parent_id
-    fn lookup<'a>(
-        &'a self,
+    /// Shared implementation of `resolve_path`/`resolve_path_nofollow`. Walks `path`
+    /// segment by segment; whenever a resolved segment is a symlink (and it isn't the
+    /// final segment with `follow_final` false), resolution restarts from the link's
+    /// target — absolute targets from root, relative targets from the link's own parent
+    /// dir — counting hops so a cycle trips `too many levels of symbolic links` instead of
+    /// looping forever.
+    #[allow(clippy::too_many_arguments)]
+    fn resolve_path_impl<'a>(
+        &'a mut self,
         uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+        follow_final: bool,
+    ) -> anyhow::Result<&'a mut Node> {
+        let root_id = self
+            .get_node(uid, groups, roles, ROOT_ID, path, prompter)?
+            .id;
+        let mut current = root_id;
+        let mut pending: VecDeque<String> = path_segments(path);
+        let mut hops = 0u32;
+
+        while let Some(name) = pending.pop_front() {
+            let parent = current;
+            let node_id = self.lookup(uid, groups, roles, parent, &name, path, prompter)?;
+            let target = self
+                .nodes
+                .get(&node_id)
+                .expect("bug: node should exist")
+                .as_symlink()
+                .ok()
+                .map(str::to_owned);
+
+            match target {
+                Some(_) if pending.is_empty() && !follow_final => current = node_id,
+                Some(target) => {
+                    hops += 1;
+                    if hops > MAX_SYMLINK_HOPS {
+                        anyhow::bail!("too many levels of symbolic links");
+                    }
+                    let target_path = PathBuf::from(&target);
+                    let mut rest = path_segments(&target_path);
+                    rest.append(&mut pending);
+                    pending = rest;
+                    current = if target_path.is_absolute() {
+                        root_id
+                    } else {
+                        parent
+                    };
+                }
+                None => current = node_id,
+            }
+        }
+        Ok(self
+            .nodes
+            .get_mut(&current)
+            .expect("bug: node should exist"))
+    }
+
+    /// Lookup the id of an entry `name` inside the directory `parent_id`.
+    /// Checks permissions on the parent and on the resolved node.
+    #[allow(clippy::too_many_arguments)]
+    fn lookup(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
         parent_id: NodeId,
         name: &str,
-    ) -> anyhow::Result<&'a Node> {
-        let dir = self.get_node(uid, parent_id)?.as_dir()?;
-        let node_id = dir.lookup(name)?;
-        self.get_node(uid, node_id)
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<NodeId> {
+        let node_id = self
+            .get_node(uid, groups, roles, parent_id, path, prompter)?
+            .as_dir()?
+            .lookup(name)?;
+        Ok(self
+            .get_node(uid, groups, roles, node_id, path, prompter)?
+            .id)
     }
 
     /// Get reference to the node with a given id
     /// Checks permissions
-    fn get_node(&self, uid: UserId, node_id: NodeId) -> anyhow::Result<&Node> {
-        let node = self.nodes.get(&node_id).expect("bug: node should exist");
-        node.check_if_allowed(uid, &[Op::Read])?;
-        Ok(node)
+    #[allow(clippy::too_many_arguments)]
+    fn get_node(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        node_id: NodeId,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<&Node> {
+        self.load_node(node_id)?;
+        let node = self
+            .nodes
+            .get_mut(&node_id)
+            .expect("bug: node should exist");
+        node.check_if_allowed(uid, groups, roles, &[Op::Read], path, prompter)?;
+        Ok(self.nodes.get(&node_id).expect("bug: node should exist"))
     }
 
     /// Get mutable reference to the node with a given id
     /// Checks permissions
-    fn get_node_mut(&mut self, uid: UserId, node_id: NodeId) -> anyhow::Result<&mut Node> {
+    #[allow(clippy::too_many_arguments)]
+    fn get_node_mut(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        node_id: NodeId,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<&mut Node> {
+        self.load_node(node_id)?;
         let node = self
             .nodes
             .get_mut(&node_id)
             .expect("bug: node should exist");
 
-        node.check_if_allowed(uid, &[Op::Write])?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Write], path, prompter)?;
+        self.dirty.insert(node_id);
         Ok(node)
     }
 
+    /// Decodes the node backing `id` into the in-memory cache if it isn't already there,
+    /// a no-op unless this `Fs` is backed by a persisted file (see [`Fs::open`]).
+    fn load_node(&mut self, id: NodeId) -> anyhow::Result<()> {
+        if self.nodes.contains_key(&id) {
+            return Ok(());
+        }
+        let Some(backing) = self.backing.as_ref() else {
+            return Ok(());
+        };
+        let &offset = backing
+            .offsets
+            .get(&id)
+            .ok_or_else(|| anyhow!("bug: node should exist"))?;
+        let body_offset = (offset + RECORD_HEADER_LEN) as usize;
+        let node: Node = bincode::deserialize(&backing.mmap[body_offset..])
+            .context("decoding persisted node record")?;
+        self.nodes.insert(id, node);
+        Ok(())
+    }
+
     /// Creates a new node in a directory with a parent_id
     /// Checks permissions
+    #[allow(clippy::too_many_arguments)]
     fn create(
         &mut self,
         uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
         parent_id: NodeId,
         name: &str,
         tag: NodeTag,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
     ) -> anyhow::Result<NodeId> {
         let mut counter = self.node_counter;
-        let parent = self.get_node_mut(uid, parent_id)?.as_dir_mut()?;
+        let parent = self
+            .get_node_mut(uid, groups, roles, parent_id, path, prompter)?
+            .as_dir_mut()?;
         if parent.contains(name) {
             anyhow::bail!("file exists");
         }
         let id = counter.next();
-        let node = Node::new_with_tag(id, parent_id, tag);
+        let node = Node::new_with_tag(id, parent_id, uid, tag);
         parent.add(name, id);
         self.nodes.insert(id, node);
+        self.dirty.insert(id);
+        self.dirty.insert(parent_id);
 
         self.node_counter = counter;
         Ok(id)
     }
 
-    /// Returns immutable reference to the parent of a given path
-    fn resolve_parent_of(&self, uid: UserId, path: &Path) -> anyhow::Result<&Node> {
-        self.resolve_path(uid, path.parent().unwrap_or_else(|| Path::new(".")))
+    /// Creates a new symlink in a directory with a parent_id, pointing at `target`.
+    /// Checks permissions. `target` is stored verbatim and only interpreted at resolution
+    /// time, so it need not exist yet.
+    #[allow(clippy::too_many_arguments)]
+    fn create_symlink(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        parent_id: NodeId,
+        name: &str,
+        target: &str,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<NodeId> {
+        let mut counter = self.node_counter;
+        let parent = self
+            .get_node_mut(uid, groups, roles, parent_id, path, prompter)?
+            .as_dir_mut()?;
+        if parent.contains(name) {
+            anyhow::bail!("file exists");
+        }
+        let id = counter.next();
+        let node = Node::new_symlink(id, uid, target.to_string());
+        parent.add(name, id);
+        self.nodes.insert(id, node);
+        self.dirty.insert(id);
+        self.dirty.insert(parent_id);
+
+        self.node_counter = counter;
+        Ok(id)
     }
 
-    /// Returns immutable reference to the parent of a given path
-    fn resolve_parent_of_mut(&mut self, uid: UserId, path: &Path) -> anyhow::Result<&mut Node> {
-        self.resolve_path_mut(uid, path.parent().unwrap_or_else(|| Path::new(".")))
+    /// Returns a mutable reference to the parent of a given path
+    fn resolve_parent_of(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<&mut Node> {
+        self.resolve_path(
+            uid,
+            groups,
+            roles,
+            path.parent().unwrap_or_else(|| Path::new(".")),
+            prompter,
+        )
     }
 
     // FS
 
-    /// Read a file
-    pub fn read<'a>(&'a self, uid: UserId, path: &Path) -> anyhow::Result<&'a str> {
-        Ok(self.resolve_path(uid, path)?.as_file()?.read())
+    /// Read a file as UTF-8 text. Fails if the file's bytes aren't valid UTF-8; use
+    /// `read_bytes` for arbitrary binary content.
+    pub fn read(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<String> {
+        let bytes = self
+            .resolve_path(uid, groups, roles, path, prompter)?
+            .as_file()?
+            .read()
+            .to_vec();
+        String::from_utf8(bytes).context("file content is not valid UTF-8")
+    }
+
+    /// Write text to a file. Overrites content
+    #[allow(clippy::too_many_arguments)]
+    pub fn write(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        data: &str,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        self.write_bytes(uid, groups, roles, path, data.as_bytes(), prompter)
+    }
+
+    /// Read a file's raw bytes, with no UTF-8 requirement.
+    pub fn read_bytes(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .resolve_path(uid, groups, roles, path, prompter)?
+            .as_file()?
+            .read()
+            .to_vec())
+    }
+
+    /// Write raw bytes to a file. Overwrites content.
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_bytes(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        data: &[u8],
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Write], path, prompter)?;
+        let id = node.id;
+        node.as_file_mut()?.write(data);
+        self.dirty.insert(id);
+        Ok(())
     }
 
-    /// Write data to a file. Overrites content
-    pub fn write(&mut self, uid: UserId, path: &Path, data: &str) -> anyhow::Result<()> {
-        let node = self.resolve_path_mut(uid, path)?.as_file_mut()?;
-        node.write(data);
+    /// Appends one fragment of a chunked upload, so a large file can be sent to the server
+    /// as a sequence of `offset`+`data` pairs instead of one buffered-in-memory write.
+    /// `offset` must equal the file's current size; chunks are expected in order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn append_bytes(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        offset: u64,
+        data: &[u8],
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Write], path, prompter)?;
+        let id = node.id;
+        node.as_file_mut()?.append(offset, data)?;
+        self.dirty.insert(id);
         Ok(())
     }
 
     /// Remove node and all subnodes
     fn rm_recursive(&mut self, id: NodeId) {
+        let _ = self.load_node(id);
         if let Some(node) = self.nodes.remove(&id) {
+            self.dirty.remove(&id);
+            self.removed.insert(id);
             if let NodeKind::Dir(dir) = node.kind {
                 dir.entries()
                     .filter(|(name, _)| !matches!(*name, "." | ".."))
@@ -334,72 +852,253 @@ impl Fs {
     }
 
     /// Remove node. Returns error, if a node is a non-empty directory
-    pub fn rm(&mut self, uid: UserId, path: &Path) -> anyhow::Result<()> {
+    pub fn rm(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
         let name = filename(path)?;
-        let parent = self.resolve_parent_of(uid, path)?;
+        let parent_id = self
+            .resolve_parent_of(uid, groups, roles, path, prompter)?
+            .id;
 
-        let id = parent.as_dir()?.lookup(name)?;
-        let node = self.get_node(uid, id)?;
+        let id = self
+            .nodes
+            .get(&parent_id)
+            .expect("bug: node should exist")
+            .as_dir()?
+            .lookup(name)?;
+        let node = self.get_node(uid, groups, roles, id, path, prompter)?;
         if let NodeKind::Dir(dir) = &node.kind {
             if !dir.is_empty() {
                 anyhow::bail!("can't remove non-empty directory")
             }
         }
 
-        let parent_id = parent.id;
-        let parent = self.get_node_mut(uid, parent_id)?.as_dir_mut()?;
+        let parent = self
+            .get_node_mut(uid, groups, roles, parent_id, path, prompter)?
+            .as_dir_mut()?;
         let old = parent.rm(name).expect("should exists");
         self.rm_recursive(old);
         Ok(())
     }
 
     /// Creates new file in a given path. Returns error if the path exists
-    pub fn new_file(&mut self, uid: UserId, path: &Path) -> anyhow::Result<()> {
-        let parent_id = self.resolve_parent_of_mut(uid, path)?.id;
+    pub fn new_file(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let parent_id = self
+            .resolve_parent_of(uid, groups, roles, path, prompter)?
+            .id;
         let name = filename(path)?;
-        self.create(uid, parent_id, name, NodeTag::File)?;
+        self.create(
+            uid,
+            groups,
+            roles,
+            parent_id,
+            name,
+            NodeTag::File,
+            path,
+            prompter,
+        )?;
         Ok(())
     }
 
     /// Creates new directory in a given path. Returns error if the path exists
-    pub fn new_dir(&mut self, uid: UserId, path: &Path) -> anyhow::Result<()> {
-        let parent_id = self.resolve_parent_of_mut(uid, path)?.id;
+    pub fn new_dir(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let parent_id = self
+            .resolve_parent_of(uid, groups, roles, path, prompter)?
+            .id;
         let name = filename(path)?;
-        self.create(uid, parent_id, name, NodeTag::Dir)?;
+        self.create(
+            uid,
+            groups,
+            roles,
+            parent_id,
+            name,
+            NodeTag::Dir,
+            path,
+            prompter,
+        )?;
+        Ok(())
+    }
+
+    /// Creates a symlink at `path` pointing at `target`. Returns error if the path exists.
+    /// `target` isn't validated against the tree; a dangling or cyclic target only
+    /// surfaces as an error the next time something resolves through the link.
+    #[allow(clippy::too_many_arguments)]
+    pub fn symlink(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        target: &str,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let parent_id = self
+            .resolve_parent_of(uid, groups, roles, path, prompter)?
+            .id;
+        let name = filename(path)?;
+        self.create_symlink(uid, groups, roles, parent_id, name, target, path, prompter)?;
         Ok(())
     }
 
     /// Executes file in a given path
-    pub fn exec(&mut self, uid: UserId, path: &Path) -> anyhow::Result<()> {
-        let node = self.resolve_path(uid, path)?;
-        node.check_if_allowed(uid, &[Op::Exec])
+    pub fn exec(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Exec], path, prompter)
     }
 
-    /// Sets permissions of a given
+    /// Sets the owner/group/other permission triple of a node. Requires `Op::Control` on the
+    /// node (admin always has it).
+    #[allow(clippy::too_many_arguments)]
     pub fn set_perms(
         &mut self,
         uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
         path: &Path,
-        perms: impl Into<Perms>,
+        perms: Perms,
+        prompter: &mut dyn PermissionPrompter,
     ) -> anyhow::Result<()> {
-        let node = self.resolve_path_mut(uid, path)?;
-        node.check_if_allowed(uid, &[Op::Control])?;
-        node.set_perm(uid, perms);
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Control], path, prompter)?;
+        let id = node.id;
+        node.set_perms(perms);
+        self.dirty.insert(id);
         Ok(())
     }
 
-    /// List entries in a directory
-    pub fn ls(&self, uid: UserId, path: &Path) -> anyhow::Result<Vec<NodeEntry>> {
-        let dir = self.resolve_path(uid, path)?.as_dir()?;
-        let mut entries = Vec::with_capacity(dir.len());
+    /// Changes the owning user and/or group of a node. Requires `Op::Control` on the node
+    /// (admin always has it).
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_owner(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        owner: Option<UserId>,
+        group: Option<GroupId>,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Control], path, prompter)?;
+        let id = node.id;
+        if let Some(owner) = owner {
+            node.set_owner(owner);
+        }
+        if let Some(group) = group {
+            node.set_group(group);
+        }
+        self.dirty.insert(id);
+        Ok(())
+    }
+
+    /// Sets or clears a node's dotted capability label, consulted by `check_if_allowed`
+    /// before falling back to the owner/group/other permission triple. Requires
+    /// `Op::Control` on the node (admin always has it).
+    pub fn set_label(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        label: Option<String>,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<()> {
+        let node = self.resolve_path(uid, groups, roles, path, prompter)?;
+        node.check_if_allowed(uid, groups, roles, &[Op::Control], path, prompter)?;
+        let id = node.id;
+        node.set_label(label);
+        self.dirty.insert(id);
+        Ok(())
+    }
+
+    /// Reports whether `path` resolves to any node (file, dir, or symlink), without
+    /// requiring it to be a directory the way `ls` does. A symlink at `path` itself counts
+    /// as existing even if its target is dangling.
+    pub fn exists(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> bool {
+        self.resolve_path_nofollow(uid, groups, roles, path, prompter)
+            .is_ok()
+    }
 
-        for (name, id) in dir.entries() {
-            let entry = match self.get_node(uid, id) {
+    /// List entries in a directory
+    pub fn ls(
+        &mut self,
+        uid: UserId,
+        groups: &GroupDb,
+        roles: &RoleDb,
+        path: &Path,
+        prompter: &mut dyn PermissionPrompter,
+    ) -> anyhow::Result<Vec<NodeEntry>> {
+        // lstat-style: a symlink at `path` itself is reported, not followed.
+        let node = self.resolve_path_nofollow(uid, groups, roles, path, prompter)?;
+        if let Ok(target) = node.as_symlink() {
+            return Ok(vec![NodeEntry {
+                tag: NodeTag::Symlink,
+                name: filename(path)?.to_string(),
+                owner: node.owner,
+                group: node.group,
+                perms: node.perms,
+                label: node.label.clone(),
+                size: node.size(),
+                target: Some(target.to_string()),
+            }]);
+        }
+        node.as_dir()?;
+        let dir_id = node.id;
+        let names: Vec<(String, NodeId)> = self
+            .nodes
+            .get(&dir_id)
+            .expect("bug: node should exist")
+            .as_dir()?
+            .entries()
+            .map(|(name, id)| (name.to_string(), id))
+            .collect();
+        let mut entries = Vec::with_capacity(names.len());
+
+        for (name, id) in names {
+            let entry = match self.get_node(uid, groups, roles, id, path, prompter) {
                 Ok(node) => NodeEntry {
                     tag: node.tag(),
-                    name: name.into(),
-                    perms: node.perms_for(uid),
+                    name,
+                    owner: node.owner,
+                    group: node.group,
+                    perms: node.perms,
+                    label: node.label.clone(),
                     size: node.size(),
+                    target: node.as_symlink().ok().map(str::to_string),
                 },
                 // TODO: report somehow?
                 Err(_) => continue,
@@ -410,24 +1109,232 @@ impl Fs {
 
         Ok(entries)
     }
-}
 
-fn reduce_segments<F, T>(path: &Path, start: T, mut callback: F) -> anyhow::Result<T>
-where
-    F: FnMut(T, &str) -> anyhow::Result<T>,
-{
-    let mut element = start;
-    for segment in path.components() {
-        let name = match segment {
-            Component::Normal(str) => str.to_str().expect("string is utf8"),
-            Component::RootDir | Component::Prefix(_) => continue,
-            Component::CurDir => ".",
-            Component::ParentDir => "..",
+    // Persistence
+
+    /// Opens a filesystem previously written by `save`/`save_as`, memory-mapping the data
+    /// file and indexing node offsets without eagerly decoding any node bodies. A node is
+    /// only deserialized the first time `get_node`/`get_node_mut` resolves its id.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("opening {:?}", path))?;
+        let mmap = unsafe { Mmap::map(&file) }.context("memory-mapping persistence file")?;
+
+        let header: FileHeader = bincode::deserialize(&mmap[..HEADER_LEN as usize])
+            .context("reading persistence header")?;
+
+        let mut offsets = HashMap::new();
+        let mut cursor = HEADER_LEN;
+        while cursor < header.total_bytes {
+            let len = u32::from_le_bytes(
+                mmap[cursor as usize..cursor as usize + 4]
+                    .try_into()
+                    .expect("4-byte length prefix"),
+            ) as u64;
+            let body_offset = cursor + 4;
+            let id = NodeId(u64::from_le_bytes(
+                mmap[body_offset as usize..body_offset as usize + 8]
+                    .try_into()
+                    .expect("8-byte node id"),
+            ));
+            let tombstone = mmap[(body_offset + 8) as usize] != 0;
+            if tombstone {
+                offsets.remove(&id);
+            } else {
+                offsets.insert(id, body_offset);
+            }
+            cursor = body_offset + len;
+        }
+
+        Ok(Self {
+            nodes: HashMap::new(),
+            node_counter: header.node_counter,
+            backing: Some(Backing {
+                file,
+                mmap,
+                path: path.to_path_buf(),
+                header,
+                offsets,
+            }),
+            dirty: HashSet::new(),
+            removed: HashSet::new(),
+        })
+    }
+
+    /// Establishes `path` as this filesystem's backing store if it doesn't have one yet
+    /// (creating the file and seeding it with every currently-loaded node), then behaves
+    /// like `save`.
+    pub fn save_as(&mut self, path: &Path) -> anyhow::Result<()> {
+        if self.backing.is_none() {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .with_context(|| format!("creating {:?}", path))?;
+            let header = FileHeader {
+                node_counter: self.node_counter,
+                root_offset: 0,
+                unreachable_bytes: 0,
+                total_bytes: HEADER_LEN,
+            };
+            write_header(&mut file, &header)?;
+            file.sync_all()?;
+            let mmap = unsafe { Mmap::map(&file) }.context("memory-mapping persistence file")?;
+            self.backing = Some(Backing {
+                file,
+                mmap,
+                path: path.to_path_buf(),
+                header,
+                offsets: HashMap::new(),
+            });
+            self.dirty = self.nodes.keys().copied().collect();
+        }
+        self.save()
+    }
+
+    /// Appends changed/new nodes to the backing file, compacting first if more than half of
+    /// it is dead (superseded or removed) bytes.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let needs_compaction = match self.backing.as_ref() {
+            Some(backing) if backing.header.total_bytes > 0 => {
+                backing.header.unreachable_bytes as f64 / backing.header.total_bytes as f64 > 0.5
+            }
+            _ => false,
+        };
+        if needs_compaction {
+            self.compact()?;
+        }
+        self.flush()
+    }
+
+    /// Appends every dirty/removed node as a new record, rewriting only the parent
+    /// directories whose child list actually changed rather than the whole tree.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let removed: Vec<NodeId> = self.removed.drain().collect();
+        let dirty: Vec<NodeId> = self.dirty.drain().collect();
+        let node_counter = self.node_counter;
+        let nodes = &self.nodes;
+
+        let backing = self
+            .backing
+            .as_mut()
+            .ok_or_else(|| anyhow!("filesystem has no backing store to save to"))?;
+
+        for id in removed {
+            let (_, len) = append_record(&mut backing.file, id, None)?;
+            backing.header.total_bytes += len;
+            if let Some(old_offset) = backing.offsets.remove(&id) {
+                let old_len = 4 + u32::from_le_bytes(
+                    backing.mmap[old_offset as usize - 4..old_offset as usize]
+                        .try_into()
+                        .expect("4-byte length prefix"),
+                ) as u64;
+                backing.header.unreachable_bytes += old_len + len;
+            }
+        }
+        for id in dirty {
+            let Some(node) = nodes.get(&id) else {
+                continue; // removed again before this flush; nothing left to persist
+            };
+            let (body_offset, len) = append_record(&mut backing.file, id, Some(node))?;
+            backing.header.total_bytes += len;
+            if let Some(old_offset) = backing.offsets.insert(id, body_offset) {
+                let old_len = 4 + u32::from_le_bytes(
+                    backing.mmap[old_offset as usize - 4..old_offset as usize]
+                        .try_into()
+                        .expect("4-byte length prefix"),
+                ) as u64;
+                backing.header.unreachable_bytes += old_len;
+            }
+            if id == ROOT_ID {
+                backing.header.root_offset = body_offset;
+            }
+        }
+
+        backing.header.node_counter = node_counter;
+        write_header(&mut backing.file, &backing.header)?;
+        backing.file.sync_all()?;
+        backing.mmap = unsafe { Mmap::map(&backing.file) }.context("remapping persistence file")?;
+        Ok(())
+    }
+
+    /// Rewrites the whole backing file from the in-memory tree, reclaiming the space held by
+    /// superseded and removed records. Loads every not-yet-cached node first, since a
+    /// compaction has to carry the full tree forward.
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        if let Some(backing) = self.backing.as_mut() {
+            for id in &self.removed {
+                backing.offsets.remove(id);
+            }
+        }
+        self.load_all()?;
+        self.dirty.clear();
+        self.removed.clear();
+
+        let path = self
+            .backing
+            .as_ref()
+            .ok_or_else(|| anyhow!("filesystem has no backing store to compact"))?
+            .path
+            .clone();
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("rewriting {:?}", path))?;
+        file.seek(SeekFrom::Start(HEADER_LEN))?;
+
+        let mut offsets = HashMap::with_capacity(self.nodes.len());
+        let mut root_offset = 0;
+        for (&id, node) in &self.nodes {
+            let (body_offset, _) = append_record(&mut file, id, Some(node))?;
+            offsets.insert(id, body_offset);
+            if id == ROOT_ID {
+                root_offset = body_offset;
+            }
+        }
+        let total_bytes = file.stream_position()?;
+
+        let header = FileHeader {
+            node_counter: self.node_counter,
+            root_offset,
+            unreachable_bytes: 0,
+            total_bytes,
         };
+        write_header(&mut file, &header)?;
+        file.sync_all()?;
+        let mmap = unsafe { Mmap::map(&file) }.context("remapping persistence file")?;
+
+        self.backing = Some(Backing {
+            file,
+            mmap,
+            path,
+            header,
+            offsets,
+        });
+        Ok(())
+    }
 
-        element = callback(element, name)?;
+    /// Decodes every node still only present in the backing file, used before `compact`
+    /// needs the complete tree in memory.
+    fn load_all(&mut self) -> anyhow::Result<()> {
+        let ids: Vec<NodeId> = match &self.backing {
+            Some(backing) => backing.offsets.keys().copied().collect(),
+            None => return Ok(()),
+        };
+        for id in ids {
+            self.load_node(id)?;
+        }
+        Ok(())
     }
-    Ok(element)
 }
 
 fn filename(path: &Path) -> anyhow::Result<&str> {
@@ -438,24 +1345,49 @@ fn filename(path: &Path) -> anyhow::Result<&str> {
         .expect("valid utf8"))
 }
 
+/// Breaks `path` into the owned segment names `resolve_path_impl` walks, in order,
+/// dropping the leading root/prefix marker (traversal always starts from a known node:
+/// either the filesystem root or, when splicing in a symlink target, its parent dir).
+fn path_segments(path: &Path) -> VecDeque<String> {
+    path.components()
+        .filter_map(|segment| match segment {
+            Component::Normal(str) => Some(str.to_str().expect("string is utf8").to_string()),
+            Component::RootDir | Component::Prefix(_) => None,
+            Component::CurDir => Some(".".to_string()),
+            Component::ParentDir => Some("..".to_string()),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
 
-    use crate::users::ADMIN_ID;
+    use crate::{
+        roles::RoleDb,
+        users::{GroupDb, ADMIN_ID},
+    };
 
     use super::*;
 
     #[test]
     fn create_write_read() {
         let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
         let uid = ADMIN_ID;
-        fs.new_dir(uid, Path::new("/dir")).unwrap();
-        fs.new_file(uid, Path::new("/dir/file")).unwrap();
+        let p = &mut NonInteractivePrompter;
+        fs.new_dir(uid, &groups, &roles, Path::new("/dir"), p)
+            .unwrap();
+        fs.new_file(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
 
         let data = "42";
-        fs.write(uid, Path::new("/dir/file"), data).unwrap();
+        fs.write(uid, &groups, &roles, Path::new("/dir/file"), data, p)
+            .unwrap();
 
-        let content = fs.read(uid, Path::new("/dir/file")).unwrap();
+        let content = fs
+            .read(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
 
         assert_eq!(content, data);
     }
@@ -463,31 +1395,268 @@ mod tests {
     #[test]
     fn create_write_rm_read() {
         let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
         let uid = ADMIN_ID;
-        fs.new_dir(uid, Path::new("/dir")).unwrap();
-        fs.new_file(uid, Path::new("/dir/file")).unwrap();
+        let p = &mut NonInteractivePrompter;
+        fs.new_dir(uid, &groups, &roles, Path::new("/dir"), p)
+            .unwrap();
+        fs.new_file(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
 
         let data = "42";
-        fs.write(uid, Path::new("/dir/file"), data).unwrap();
+        fs.write(uid, &groups, &roles, Path::new("/dir/file"), data, p)
+            .unwrap();
 
-        let content = fs.read(uid, Path::new("/dir/file")).unwrap();
+        let content = fs
+            .read(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
 
         assert_eq!(content, data);
 
-        let res = fs.rm(uid, Path::new("/dir"));
+        let res = fs.rm(uid, &groups, &roles, Path::new("/dir"), p);
         assert!(res.is_err());
     }
 
     #[test]
     fn create_access_read() {
         let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.new_file(uid, &groups, &roles, Path::new("./file"), p)
+            .unwrap();
+
+        fs.write(uid, &groups, &roles, Path::new("file"), "my fancy data", p)
+            .unwrap();
+
+        fs.read(UserId::new(12), &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn role_label_bypasses_perms() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let mut roles = RoleDb::new();
+        let p = &mut NonInteractivePrompter;
+        let uid = UserId::new(12);
+
+        fs.new_file(ADMIN_ID, &groups, &roles, Path::new("/file"), p)
+            .unwrap();
+        fs.set_label(
+            ADMIN_ID,
+            &groups,
+            &roles,
+            Path::new("/file"),
+            Some("fs.home.file".to_string()),
+            p,
+        )
+        .unwrap();
+        // Let the user traverse `/` itself; `/file`'s own perms stay default-deny.
+        let root_perms: Perms = "---- ---- r---".parse().unwrap();
+        fs.set_perms(ADMIN_ID, &groups, &roles, Path::new("/"), root_perms, p)
+            .unwrap();
+
+        // Without a matching role, an unrelated user is denied (default-deny `Mode`).
+        fs.read(uid, &groups, &roles, Path::new("/file"), p)
+            .unwrap_err();
+
+        roles
+            .add_role("reader", vec!["fs.home.*".to_string()], vec![])
+            .unwrap();
+        roles.assign(uid, "reader").unwrap();
+
+        fs.read(uid, &groups, &roles, Path::new("/file"), p)
+            .unwrap();
+    }
+
+    #[test]
+    fn symlink_resolves_through_to_target() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.new_dir(uid, &groups, &roles, Path::new("/dir"), p)
+            .unwrap();
+        fs.new_file(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
+        fs.write(uid, &groups, &roles, Path::new("/dir/file"), "42", p)
+            .unwrap();
+
+        fs.symlink(uid, &groups, &roles, Path::new("/link"), "/dir/file", p)
+            .unwrap();
+
+        let content = fs.read(uid, &groups, &roles, Path::new("/link"), p).unwrap();
+        assert_eq!(content, "42");
+    }
+
+    #[test]
+    fn symlink_cycle_errors_instead_of_looping() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.symlink(uid, &groups, &roles, Path::new("/a"), "/b", p)
+            .unwrap();
+        fs.symlink(uid, &groups, &roles, Path::new("/b"), "/a", p)
+            .unwrap();
+
+        let err = fs
+            .read(uid, &groups, &roles, Path::new("/a"), p)
+            .unwrap_err();
+        assert!(err.to_string().contains("too many levels of symbolic links"));
+    }
+
+    #[test]
+    fn ls_reports_symlink_itself_without_following() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.new_file(uid, &groups, &roles, Path::new("/file"), p)
+            .unwrap();
+        fs.symlink(uid, &groups, &roles, Path::new("/link"), "/file", p)
+            .unwrap();
+
+        let entries = fs.ls(uid, &groups, &roles, Path::new("/link"), p).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(entries[0].tag, NodeTag::Symlink));
+        assert_eq!(entries[0].target.as_deref(), Some("/file"));
+    }
+
+    #[test]
+    fn write_bytes_read_bytes_roundtrip() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.new_file(uid, &groups, &roles, Path::new("/blob"), p)
+            .unwrap();
+
+        let data = vec![0u8, 159, 146, 150, 255];
+        fs.write_bytes(uid, &groups, &roles, Path::new("/blob"), &data, p)
+            .unwrap();
+
+        let content = fs
+            .read_bytes(uid, &groups, &roles, Path::new("/blob"), p)
+            .unwrap();
+        assert_eq!(content, data);
+    }
+
+    #[test]
+    fn append_bytes_assembles_chunks_in_order() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        fs.new_file(uid, &groups, &roles, Path::new("/blob"), p)
+            .unwrap();
+
+        fs.append_bytes(uid, &groups, &roles, Path::new("/blob"), 0, b"hello", p)
+            .unwrap();
+        fs.append_bytes(uid, &groups, &roles, Path::new("/blob"), 5, b" world", p)
+            .unwrap();
+
+        let content = fs
+            .read_bytes(uid, &groups, &roles, Path::new("/blob"), p)
+            .unwrap();
+        assert_eq!(content, b"hello world");
+    }
+
+    #[test]
+    fn persistence_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "filesys-persistence-round-trip-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
+
+        {
+            let mut fs = Fs::new();
+            fs.new_dir(uid, &groups, &roles, Path::new("/dir"), p)
+                .unwrap();
+            fs.new_file(uid, &groups, &roles, Path::new("/dir/file"), p)
+                .unwrap();
+            fs.write(uid, &groups, &roles, Path::new("/dir/file"), "42", p)
+                .unwrap();
+            fs.save_as(&path).unwrap();
+        }
+
+        let mut reopened = Fs::open(&path).unwrap();
+        let content = reopened
+            .read(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
+        assert_eq!(content, "42");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn save_after_reads_only_appends_dirty_nodes() {
+        let path = std::env::temp_dir().join(format!(
+            "filesys-save-after-reads-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
         let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
 
-        fs.new_file(uid, Path::new("./file")).unwrap();
+        let mut fs = Fs::new();
+        fs.new_dir(uid, &groups, &roles, Path::new("/dir"), p)
+            .unwrap();
+        fs.new_file(uid, &groups, &roles, Path::new("/dir/file"), p)
+            .unwrap();
+        fs.save_as(&path).unwrap();
+        let size_after_create = std::fs::metadata(&path).unwrap().len();
+
+        for _ in 0..5 {
+            fs.read(uid, &groups, &roles, Path::new("/dir/file"), p)
+                .unwrap();
+            fs.ls(uid, &groups, &roles, Path::new("/dir"), p).unwrap();
+        }
+        fs.save().unwrap();
+        let size_after_reads = std::fs::metadata(&path).unwrap().len();
+
+        assert_eq!(size_after_create, size_after_reads);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn append_bytes_rejects_out_of_order_chunk() {
+        let mut fs = Fs::new();
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
+        let uid = ADMIN_ID;
+        let p = &mut NonInteractivePrompter;
 
-        fs.write(uid, Path::new("file"), "my fancy data").unwrap();
+        fs.new_file(uid, &groups, &roles, Path::new("/blob"), p)
+            .unwrap();
 
-        fs.read(UserId::new(12), Path::new("/dir/file"))
+        let err = fs
+            .append_bytes(uid, &groups, &roles, Path::new("/blob"), 3, b"abc", p)
             .unwrap_err();
+        assert!(err.to_string().contains("out-of-order chunk"));
     }
 }
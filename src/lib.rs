@@ -1,6 +1,9 @@
+pub mod auth;
+pub mod bootstrap;
 pub mod fs;
 pub mod log;
 pub mod protocol;
+pub mod roles;
 pub mod users;
 
 use std::{
@@ -9,11 +12,13 @@ use std::{
     time::{Duration, Instant},
 };
 
-use fs::{Fs, NodeEntry};
-use log::{Log, Logger};
+use fs::{Fs, NodeEntry, NonInteractivePrompter};
+use log::{Log, LogFilter, LogLevel, Logger};
 use once_cell::sync::Lazy;
+use roles::RoleDb;
+use rocket::time::OffsetDateTime;
 use serde::{Deserialize, Serialize};
-use users::{Perms, UserDb, UserId, Username, ADMIN_ID};
+use users::{GroupDb, Perms, UserDb, UserId, Username};
 
 static INACTIVITY_TIMEOUT: Lazy<Duration> = Lazy::new(|| Duration::new(60, 0));
 
@@ -25,12 +30,22 @@ pub enum Action {
     NewFile(PathBuf),
     NewDir(PathBuf),
     Exec(PathBuf),
-    SetPerms(PathBuf, Username, Perms),
+    SetPerms(PathBuf, Perms),
     Ls(PathBuf),
+    ReadBytes(PathBuf),
+    WriteBytes(PathBuf, Vec<u8>),
+    /// Appends one chunk of a larger upload at `offset`, so `/upload` can stream a file to
+    /// the server as a sequence of fragments instead of one buffered `WriteBytes`.
+    AppendBytes(PathBuf, u64, Vec<u8>),
     AddUser(Username),
     ChangePassword { old: String, new: String },
     Unblock(Username),
-    Logs,
+    Logs {
+        level: Option<LogLevel>,
+        since: Option<OffsetDateTime>,
+        uid: Option<UserId>,
+        limit: Option<usize>,
+    },
 }
 
 impl std::fmt::Display for Action {
@@ -42,14 +57,34 @@ impl std::fmt::Display for Action {
             Action::NewFile(path) => write!(f, "new-file({:?})", path),
             Action::NewDir(path) => write!(f, "new-dir({:?})", path),
             Action::Exec(path) => write!(f, "exec({:?})", path),
-            Action::SetPerms(path, user, perms) => {
-                write!(f, "set-perms({:?}, {:?}, {})", path, user, perms)
+            Action::SetPerms(path, perms) => {
+                write!(f, "set-perms({:?}, {})", path, perms)
             }
             Action::Ls(path) => write!(f, "ls({:?})", path),
+            Action::ReadBytes(path) => write!(f, "read-bytes({:?})", path),
+            Action::WriteBytes(path, data) => {
+                write!(f, "write-bytes({:?}, {} bytes)", path, data.len())
+            }
+            Action::AppendBytes(path, offset, data) => write!(
+                f,
+                "append-bytes({:?}, offset={}, {} bytes)",
+                path,
+                offset,
+                data.len()
+            ),
             Action::AddUser(user) => write!(f, "add-user({:?})", user),
             Action::ChangePassword { .. } => write!(f, "change-pass"),
             Action::Unblock(user) => write!(f, "unblock({:?})", user),
-            Action::Logs => write!(f, "logs"),
+            Action::Logs {
+                level,
+                since,
+                uid,
+                limit,
+            } => write!(
+                f,
+                "logs(level={:?}, since={:?}, uid={:?}, limit={:?})",
+                level, since, uid, limit
+            ),
         }
     }
 }
@@ -58,6 +93,7 @@ impl std::fmt::Display for Action {
 pub enum ActionRes {
     Ok,
     Read(String),
+    ReadBytes(Vec<u8>),
     Ls(Vec<NodeEntry>),
     Logs(Vec<Log>),
 }
@@ -66,6 +102,8 @@ pub enum ActionRes {
 pub struct System {
     fs: Fs,
     users: UserDb,
+    groups: GroupDb,
+    roles: RoleDb,
     last_access: HashMap<UserId, Instant>,
 }
 
@@ -73,9 +111,13 @@ impl System {
     pub fn new() -> anyhow::Result<Self> {
         let fs = Fs::new();
         let users = UserDb::new()?;
+        let groups = GroupDb::new();
+        let roles = RoleDb::new();
         Ok(Self {
             fs,
             users,
+            groups,
+            roles,
             last_access: HashMap::new(),
         })
     }
@@ -87,11 +129,26 @@ impl System {
         Ok(id)
     }
 
+    /// First half of checking a CRAM-style response: looks up `name`'s id and verifier
+    /// once the client has answered the nonce, without touching its failed-attempt count.
+    pub fn cram_lookup(&self, name: &str) -> anyhow::Result<(UserId, [u8; 32])> {
+        self.users.cram_lookup(name)
+    }
+
+    /// Finishes a CRAM-style login by checking `response` against the nonce handed out for
+    /// `uid`. Same bookkeeping as [`Self::login`] on success.
+    pub fn verify_cram(&mut self, uid: UserId, nonce: &[u8], response: &[u8]) -> anyhow::Result<UserId> {
+        info!("new cram login for {:?}", uid);
+        self.users.verify_cram(uid, nonce, response)?;
+        self.last_access.insert(uid, Instant::now());
+        Ok(uid)
+    }
+
     pub fn add_user(&mut self, uid: UserId, name: &str) -> anyhow::Result<UserId> {
-        if uid == ADMIN_ID {
+        if self.roles.grants(uid, "users.manage") {
             self.users.add_user(name, "")
         } else {
-            anyhow::bail!("only admin can manage users")
+            anyhow::bail!("missing permission: users.manage")
         }
     }
 
@@ -107,26 +164,68 @@ impl System {
         }
 
         let ok = |_| ActionRes::Ok;
+        // The server has no REPL to prompt through, so `Prompt`-policy ops always deny here.
+        let p = &mut NonInteractivePrompter;
 
         let res = match cmd {
             Action::Read(path) => self
                 .fs
-                .read(uid, path)
-                .map(|data| ActionRes::Read(data.into())),
-            Action::Write(path, data) => self.fs.write(uid, path, data).map(ok),
-            Action::Rm(path) => self.fs.rm(uid, path).map(ok),
-            Action::NewFile(path) => self.fs.new_file(uid, path).map(ok),
-            Action::NewDir(path) => self.fs.new_dir(uid, path).map(ok),
-            Action::Exec(path) => self.fs.exec(uid, path).map(ok),
-            Action::SetPerms(path, username, perms) => {
-                let for_user = self.users.id_of(username)?;
-                self.fs.set_perms(uid, for_user, path, *perms).map(ok)
-            }
-            Action::Ls(path) => self.fs.ls(uid, path).map(ActionRes::Ls),
+                .read(uid, &self.groups, &self.roles, path, p)
+                .map(ActionRes::Read),
+            Action::Write(path, data) => self
+                .fs
+                .write(uid, &self.groups, &self.roles, path, data, p)
+                .map(ok),
+            Action::Rm(path) => self.fs.rm(uid, &self.groups, &self.roles, path, p).map(ok),
+            Action::NewFile(path) => self
+                .fs
+                .new_file(uid, &self.groups, &self.roles, path, p)
+                .map(ok),
+            Action::NewDir(path) => self
+                .fs
+                .new_dir(uid, &self.groups, &self.roles, path, p)
+                .map(ok),
+            Action::Exec(path) => self
+                .fs
+                .exec(uid, &self.groups, &self.roles, path, p)
+                .map(ok),
+            Action::SetPerms(path, perms) => self
+                .fs
+                .set_perms(uid, &self.groups, &self.roles, path, *perms, p)
+                .map(ok),
+            Action::Ls(path) => self
+                .fs
+                .ls(uid, &self.groups, &self.roles, path, p)
+                .map(ActionRes::Ls),
+            Action::ReadBytes(path) => self
+                .fs
+                .read_bytes(uid, &self.groups, &self.roles, path, p)
+                .map(ActionRes::ReadBytes),
+            Action::WriteBytes(path, data) => self
+                .fs
+                .write_bytes(uid, &self.groups, &self.roles, path, data, p)
+                .map(ok),
+            Action::AppendBytes(path, offset, data) => self
+                .fs
+                .append_bytes(uid, &self.groups, &self.roles, path, *offset, data, p)
+                .map(ok),
             Action::AddUser(name) => self.add_user(uid, name).map(|_| ActionRes::Ok),
             Action::ChangePassword { old, new } => self.users.change_pass(uid, old, new).map(ok),
             Action::Unblock(username) => self.unblock(uid, username).map(|_| ActionRes::Ok),
-            Action::Logs => self.logs(uid).map(ActionRes::Logs),
+            Action::Logs {
+                level,
+                since,
+                uid: filter_uid,
+                limit,
+            } => {
+                let filter = LogFilter {
+                    level: *level,
+                    since: *since,
+                    uid: *filter_uid,
+                    limit: *limit,
+                };
+                self.logs(uid, filter).map(ActionRes::Logs)
+            }
         };
 
         info!(uid => "action {} => {:?}", cmd, res.as_ref().map(|_| ()));
@@ -134,11 +233,23 @@ impl System {
     }
 
     fn unblock(&mut self, uid: UserId, username: &str) -> anyhow::Result<()> {
-        if uid != ADMIN_ID {
-            anyhow::bail!("only admin can unblock the user")
-        } else {
-            self.users.unblock(username)
+        if !self.roles.grants(uid, "users.unblock") {
+            anyhow::bail!("missing permission: users.unblock")
         }
+        self.users.unblock(username)
+    }
+
+    /// Declaratively provisions `config` (see [`bootstrap::Config`]) straight into this
+    /// system's roles, users, and filesystem, bypassing [`Self::exec`]'s inactivity-timeout
+    /// gate since this runs at boot, before anyone has logged in.
+    pub fn bootstrap(&mut self, config: &bootstrap::Config) -> anyhow::Result<()> {
+        bootstrap::apply(
+            config,
+            &mut self.users,
+            &self.groups,
+            &mut self.roles,
+            &mut self.fs,
+        )
     }
 
     pub fn pack(self) -> SystemImage {
@@ -147,16 +258,24 @@ impl System {
         let System {
             fs,
             users,
+            groups,
+            roles,
             last_access: _,
         } = self;
-        SystemImage { fs, users, logger }
+        SystemImage {
+            fs,
+            users,
+            groups,
+            roles,
+            logger,
+        }
     }
 
-    pub fn logs(&self, uid: UserId) -> anyhow::Result<Vec<Log>> {
-        if uid == ADMIN_ID {
-            Ok(log::logger().logs().to_owned())
+    pub fn logs(&self, uid: UserId, filter: LogFilter) -> anyhow::Result<Vec<Log>> {
+        if self.roles.grants(uid, "logs.read") {
+            Ok(log::logger().query(&filter))
         } else {
-            anyhow::bail!("only admin can view the logs")
+            anyhow::bail!("missing permission: logs.read")
         }
     }
 }
@@ -165,18 +284,28 @@ impl System {
 pub struct SystemImage {
     fs: Fs,
     users: UserDb,
+    groups: GroupDb,
+    roles: RoleDb,
     logger: Logger,
 }
 
 impl SystemImage {
     pub fn unpack(self) -> System {
-        let SystemImage { fs, users, logger } = self;
+        let SystemImage {
+            fs,
+            users,
+            groups,
+            roles,
+            logger,
+        } = self;
 
         log::set_logger(logger);
 
         System {
             fs,
             users,
+            groups,
+            roles,
             last_access: HashMap::new(),
         }
     }
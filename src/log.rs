@@ -1,5 +1,7 @@
 use std::{
+    collections::VecDeque,
     fmt::Debug,
+    str::FromStr,
     sync::{Mutex, MutexGuard},
 };
 
@@ -12,7 +14,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::users::UserId;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Ordered `Debug < Info < Error`, so a minimum-severity filter can just compare levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum LogLevel {
     Debug,
     Info,
@@ -30,6 +33,19 @@ impl std::fmt::Display for LogLevel {
     }
 }
 
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "error" => Ok(LogLevel::Error),
+            _ => anyhow::bail!("unknown log level: {:?}", s),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Log {
     level: LogLevel,
@@ -75,19 +91,104 @@ impl Log {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Narrows a [`Logger::query`]: `level` is a *minimum* severity (`Debug < Info < Error`),
+/// `since` and `uid` restrict by time and acting user, and `limit` caps how many of the
+/// most recent matches come back.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogFilter {
+    pub level: Option<LogLevel>,
+    pub since: Option<OffsetDateTime>,
+    pub uid: Option<UserId>,
+    pub limit: Option<usize>,
+}
+
+impl LogFilter {
+    fn matches(&self, log: &Log) -> bool {
+        if let Some(level) = self.level {
+            if log.level < level {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if log.time < since {
+                return false;
+            }
+        }
+        if let Some(uid) = self.uid {
+            if log.uid != Some(uid) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The default number of entries a fresh [`Logger`] keeps before evicting the oldest one,
+/// used whenever a server doesn't ask for a different capacity via `--log-capacity`.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// A ring buffer of the most recent [`Log`] entries: once `capacity` is reached, logging a
+/// new entry evicts the oldest one. Keeps both in-memory size and `SystemImage`'s on-disk
+/// size bounded, unlike the unbounded `Vec` this replaced.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Logger {
-    logs: Vec<Log>,
+    logs: VecDeque<Log>,
+    capacity: usize,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
 }
 
 impl Logger {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            logs: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Resizes the ring buffer, evicting the oldest entries first if it's shrinking.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        while self.logs.len() > capacity {
+            self.logs.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
     pub fn log(&mut self, log: Log) {
-        self.logs.push(log);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.logs.len() >= self.capacity {
+            self.logs.pop_front();
+        }
+        self.logs.push_back(log);
+    }
+
+    /// Get a reference to the logger's logs, oldest first.
+    pub fn logs(&self) -> impl Iterator<Item = &Log> {
+        self.logs.iter()
     }
 
-    /// Get a reference to the logger's logs.
-    pub fn logs(&self) -> &[Log] {
-        self.logs.as_ref()
+    /// Returns the entries matching `filter`, oldest first, keeping only the most recent
+    /// `filter.limit` of them if it's set.
+    pub fn query(&self, filter: &LogFilter) -> Vec<Log> {
+        let mut matched: Vec<Log> = self
+            .logs
+            .iter()
+            .filter(|log| filter.matches(log))
+            .cloned()
+            .collect();
+        if let Some(limit) = filter.limit {
+            let len = matched.len();
+            if len > limit {
+                matched.drain(0..len - limit);
+            }
+        }
+        matched
     }
 }
 
@@ -107,6 +208,65 @@ pub fn logger() -> MutexGuard<'static, Logger> {
     LOGGER.lock().expect("acquiring mutex")
 }
 
+/// Resizes the live logger's ring buffer, e.g. right after loading a `SystemImage` whose
+/// persisted capacity an operator wants to override with `--log-capacity`.
+pub fn set_capacity(capacity: usize) {
+    logger().set_capacity(capacity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_evicts_oldest_past_capacity() {
+        let mut logger = Logger::with_capacity(2);
+        logger.log(Log::new(LogLevel::Info, "first".to_string()));
+        logger.log(Log::new(LogLevel::Info, "second".to_string()));
+        logger.log(Log::new(LogLevel::Info, "third".to_string()));
+
+        let msgs: Vec<&str> = logger.logs().map(|log| log.msg.as_str()).collect();
+        assert_eq!(msgs, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn query_filters_by_minimum_level_and_uid() {
+        let mut logger = Logger::with_capacity(10);
+        let uid = UserId::new(1);
+        let other = UserId::new(2);
+        logger.log(Log::new_with_uid(LogLevel::Debug, uid, "debug for uid".to_string()));
+        logger.log(Log::new_with_uid(LogLevel::Error, uid, "error for uid".to_string()));
+        logger.log(Log::new_with_uid(LogLevel::Error, other, "error for other".to_string()));
+
+        let filter = LogFilter {
+            level: Some(LogLevel::Info),
+            uid: Some(uid),
+            ..Default::default()
+        };
+        let matched = logger.query(&filter);
+
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].msg, "error for uid");
+    }
+
+    #[test]
+    fn query_limit_keeps_only_the_most_recent_matches() {
+        let mut logger = Logger::with_capacity(10);
+        for i in 0..5 {
+            logger.log(Log::new(LogLevel::Info, format!("msg {}", i)));
+        }
+
+        let filter = LogFilter {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let matched = logger.query(&filter);
+
+        let msgs: Vec<&str> = matched.iter().map(|log| log.msg.as_str()).collect();
+        assert_eq!(msgs, vec!["msg 3", "msg 4"]);
+    }
+}
+
 #[macro_export]
 macro_rules! debug {
     ($uid: expr =>  $($args : tt)*) => {{
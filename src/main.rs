@@ -1,12 +1,170 @@
+pub mod config;
 pub mod fs;
+pub mod roles;
 pub mod users;
 
+use std::path::{Path, PathBuf};
+
+use fs::{Fs, PermissionPrompter, PromptResponse};
+use roles::RoleDb;
+use users::{GroupDb, Op, UserDb, UserId};
+
+/// Asks the operator directly on the REPL's own terminal whenever a node's policy defers
+/// to a prompt, the CLI equivalent of Deno's `--allow-*` runtime permission dialog.
+struct RustylinePrompter<'a> {
+    rl: &'a mut rustyline::Editor<()>,
+}
+
+impl<'a> PermissionPrompter for RustylinePrompter<'a> {
+    fn prompt(&mut self, uid: UserId, path: &Path, ops: &[Op]) -> PromptResponse {
+        let ops = ops
+            .iter()
+            .map(|op| format!("{:?}", op))
+            .collect::<Vec<_>>()
+            .join("+");
+        let question = format!(
+            "\u{26a0}\u{fe0f}  {:?} wants {} on {:?} — allow? [o]nce/[a]lways/[n]o/[N]ever: ",
+            uid, ops, path
+        );
+        match self.rl.readline(&question) {
+            Ok(answer) => match answer.trim() {
+                "a" | "A" | "always" => PromptResponse::AllowAlways,
+                "N" | "never" => PromptResponse::DenyAlways,
+                "o" | "O" | "y" | "yes" => PromptResponse::AllowOnce,
+                _ => PromptResponse::DenyOnce,
+            },
+            Err(_) => PromptResponse::DenyOnce,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_command(
+    fs: &mut Fs,
+    groups: &GroupDb,
+    roles: &mut RoleDb,
+    uid: UserId,
+    parts: &[String],
+    prompter: &mut dyn PermissionPrompter,
+) -> anyhow::Result<()> {
+    let (cmd, args) = parts
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("empty command"))?;
+    let arg = |n: usize, usage: &str| -> anyhow::Result<PathBuf> {
+        args.get(n)
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("usage: {}", usage))
+    };
+
+    match cmd.as_str() {
+        "read" => println!(
+            "{}",
+            fs.read(uid, groups, roles, &arg(0, "read <path>")?, prompter)?
+        ),
+        "write" => {
+            let path = arg(0, "write <path> <data>")?;
+            let data = args.get(1..).unwrap_or_default().join(" ");
+            fs.write(uid, groups, roles, &path, &data, prompter)?;
+        }
+        "new-file" => {
+            fs.new_file(uid, groups, roles, &arg(0, "new-file <path>")?, prompter)?;
+        }
+        "new-dir" => {
+            fs.new_dir(uid, groups, roles, &arg(0, "new-dir <path>")?, prompter)?;
+        }
+        "rm" => {
+            fs.rm(uid, groups, roles, &arg(0, "rm <path>")?, prompter)?;
+        }
+        "exec" => {
+            fs.exec(uid, groups, roles, &arg(0, "exec <path>")?, prompter)?;
+        }
+        "ls" => {
+            let path = args
+                .first()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("."));
+            for entry in fs.ls(uid, groups, roles, &path, prompter)? {
+                print!("{:?} {} {}", entry.tag, entry.perms, entry.name);
+                if let Some(target) = &entry.target {
+                    print!(" -> {}", target);
+                }
+                println!();
+            }
+        }
+        "symlink" => {
+            let path = arg(0, "symlink <path> <target>")?;
+            let target = args
+                .get(1)
+                .ok_or_else(|| anyhow::anyhow!("usage: symlink <path> <target>"))?;
+            fs.symlink(uid, groups, roles, &path, target, prompter)?;
+        }
+        "label" => {
+            let path = arg(0, "label <path> [dotted.label]")?;
+            let label = args.get(1).cloned();
+            fs.set_label(uid, groups, roles, &path, label, prompter)?;
+        }
+        "add-role" => {
+            let name = args
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("usage: add-role <name> [pattern ...]"))?;
+            let patterns = args.get(1..).unwrap_or_default().to_vec();
+            roles.add_role(name, patterns, vec![])?;
+        }
+        "assign-role" => {
+            let raw_uid = args
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("usage: assign-role <uid> <role>"))?;
+            let role = args
+                .get(1)
+                .ok_or_else(|| anyhow::anyhow!("usage: assign-role <uid> <role>"))?;
+            let target = UserId::new(raw_uid.parse()?);
+            roles.assign(target, role)?;
+        }
+        other => anyhow::bail!("unknown command: {:?}", other),
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let mut rl = rustyline::Editor::<()>::new();
-    let readline = rl.readline(">> ");
-    match readline {
-        Ok(line) => println!("Line: {:?}", line),
-        Err(_) => println!("No input"),
+    let mut fs = Fs::new();
+    let groups = GroupDb::new();
+    let mut roles = RoleDb::new();
+    let mut users = UserDb::new()?;
+    // A non-root session user, so permission checks actually exercise the prompt path
+    // instead of always taking the admin bypass.
+    let uid = UserId::new(1);
+
+    if let Some(config_path) = std::env::args().nth(1) {
+        let config = config::Config::load(Path::new(&config_path))?;
+        config::apply(&config, &mut users, &groups, &roles, &mut fs)?;
+    }
+
+    loop {
+        let line = match rl.readline(">> ") {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        rl.add_history_entry(line.as_str());
+
+        let parts = match shellwords::split(&line) {
+            Ok(parts) => parts,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        if parts.is_empty() {
+            continue;
+        }
+        if parts[0] == "exit" || parts[0] == "quit" {
+            break;
+        }
+
+        let mut prompter = RustylinePrompter { rl: &mut rl };
+        if let Err(err) = run_command(&mut fs, &groups, &mut roles, uid, &parts, &mut prompter) {
+            eprintln!("{}", err);
+        }
     }
     Ok(())
 }
@@ -1,20 +1,55 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
 use crate::users::UserId;
 
+/// Claims of the stateless session token minted at login: `uid` identifies the caller,
+/// `iat`/`exp` bound how long the token is good for. Signed and verified with an HMAC
+/// secret, so a tampered or expired token is rejected before `System::exec` ever sees the
+/// `uid` inside — the server trusts the signature, not whatever cookie the client presents.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiKey {
     uid: UserId,
+    iat: u64,
+    exp: u64,
 }
 
 impl ApiKey {
-    pub fn new(uid: UserId) -> Self {
-        Self { uid }
+    pub fn new(uid: UserId, ttl_sec: i64) -> Self {
+        let iat = now_unix();
+        Self {
+            uid,
+            iat,
+            exp: iat + ttl_sec.max(0) as u64,
+        }
     }
 
     pub fn uid(&self) -> UserId {
         self.uid
     }
+
+    /// Signs this claim set into a compact JWT using `secret` as the HMAC key.
+    pub fn encode(&self, secret: &[u8]) -> anyhow::Result<String> {
+        let key = EncodingKey::from_secret(secret);
+        encode(&Header::default(), self, &key).map_err(Into::into)
+    }
+
+    /// Verifies `token`'s signature and `exp` claim against `secret`, returning the
+    /// claims only if both check out.
+    pub fn decode(token: &str, secret: &[u8]) -> anyhow::Result<Self> {
+        let key = DecodingKey::from_secret(secret);
+        let data = decode::<Self>(token, &key, &Validation::default())?;
+        Ok(data.claims)
+    }
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before epoch")
+        .as_secs()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +58,39 @@ pub struct LoginInfo {
     pub password: String,
 }
 
+/// A SASL-style login mechanism advertised by `/auth/mechanisms` and chosen by the client
+/// when opening an `/auth/step` exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mechanism {
+    /// One round: the client's only message is a base64 `\0username\0password` blob.
+    Plain,
+    /// Two rounds: the server asks for a username, then a password, in the clear.
+    Login,
+    /// Two rounds: the server hands out a nonce, the client answers with
+    /// `"username HMAC-SHA256(cram_verifier, nonce)"` (hex-encoded digest) so the password
+    /// itself never crosses the wire.
+    Cram,
+}
+
+/// One round of an `/auth/step` exchange: the first message names `mechanism` and carries
+/// its initial client data (empty where the server speaks first, e.g. `Cram`); every later
+/// round instead echoes back the `session` id from the previous challenge. `data` is
+/// mechanism-specific, base64-encoded so arbitrary bytes (nonces, digests) survive JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthStepRequest {
+    pub session: Option<String>,
+    pub mechanism: Option<Mechanism>,
+    pub data: String,
+}
+
+/// Either another challenge to relay back to the client (remembering `session` for the
+/// reply), or the finished exchange's session token, in the same shape `/login` returns.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AuthStepResponse {
+    Challenge { session: String, data: String },
+    Done { token: String },
+}
+
 pub trait IntoSerialize<T> {
     fn into_serialize(self) -> Result<T, String>;
 }
@@ -44,3 +112,31 @@ impl<T> EDeserialize<T> for Result<T, String> {
 }
 
 pub type ResResult<T> = Result<T, String>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let uid = UserId::new(7);
+        let key = ApiKey::new(uid, 3600);
+        let token = key.encode(b"secret").unwrap();
+
+        let decoded = ApiKey::decode(&token, b"secret").unwrap();
+        assert_eq!(decoded.uid(), uid);
+    }
+
+    #[test]
+    fn decode_rejects_wrong_secret() {
+        let key = ApiKey::new(UserId::new(7), 3600);
+        let token = key.encode(b"secret").unwrap();
+
+        ApiKey::decode(&token, b"wrong secret").unwrap_err();
+    }
+
+    #[test]
+    fn decode_rejects_malformed_token() {
+        ApiKey::decode("not.a.jwt", b"secret").unwrap_err();
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::users::{UserId, ADMIN_ID};
+
+pub type Rolename = String;
+
+/// A named bundle of dotted permission-pattern globs (e.g. `fs.home.*`), optionally
+/// inheriting more patterns from parent roles. Modeled on the roles/machines TOML from
+/// fabaccess, where a role like `lab.member` can extend a broader `member` role.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    patterns: Vec<String>,
+    parents: Vec<Rolename>,
+}
+
+/// Resolves a user's effective permission patterns by walking the role parent DAG, with a
+/// visited set guarding against cycles in the parent graph.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RoleDb {
+    roles: HashMap<Rolename, Role>,
+    assigned: HashMap<UserId, HashSet<Rolename>>,
+}
+
+impl RoleDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_role(
+        &mut self,
+        name: &str,
+        patterns: Vec<String>,
+        parents: Vec<Rolename>,
+    ) -> anyhow::Result<()> {
+        if self.roles.contains_key(name) {
+            anyhow::bail!("role exists")
+        }
+        self.roles
+            .insert(name.to_string(), Role { patterns, parents });
+        Ok(())
+    }
+
+    /// Whether a role named `name` has been added, for callers (like a bootstrap loader)
+    /// that want to create roles idempotently instead of treating "already exists" as an
+    /// error.
+    pub fn contains(&self, name: &str) -> bool {
+        self.roles.contains_key(name)
+    }
+
+    pub fn assign(&mut self, uid: UserId, role: &str) -> anyhow::Result<()> {
+        if !self.roles.contains_key(role) {
+            anyhow::bail!("role not found")
+        }
+        self.assigned
+            .entry(uid)
+            .or_default()
+            .insert(role.to_string());
+        Ok(())
+    }
+
+    /// Union of every pattern reachable from `uid`'s directly assigned roles, walking parents
+    /// transitively. A role already visited is skipped instead of revisited, so a cycle in
+    /// the parent graph is silently broken rather than looping forever.
+    pub fn effective_patterns(&self, uid: UserId) -> HashSet<String> {
+        let mut patterns = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut queue: Vec<Rolename> = self
+            .assigned
+            .get(&uid)
+            .map(|roles| roles.iter().cloned().collect())
+            .unwrap_or_default();
+
+        while let Some(name) = queue.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(role) = self.roles.get(&name) {
+                patterns.extend(role.patterns.iter().cloned());
+                queue.extend(role.parents.iter().cloned());
+            }
+        }
+        patterns
+    }
+
+    /// Whether any of `uid`'s effective patterns glob-matches `label`. Admin is implicitly
+    /// granted everything, the same bypass `GroupDb::is_member` gives it.
+    pub fn grants(&self, uid: UserId, label: &str) -> bool {
+        if uid == ADMIN_ID {
+            return true;
+        }
+        self.effective_patterns(uid)
+            .iter()
+            .any(|pattern| glob_match(pattern, label))
+    }
+}
+
+/// Segment-wise glob match on `.`-separated strings: `*` matches exactly one segment, and a
+/// trailing `*` matches that segment plus everything after it (so `fs.home.*` matches both
+/// `fs.home` and `fs.home.a.b`).
+pub(crate) fn glob_match(pattern: &str, label: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let label: Vec<&str> = label.split('.').collect();
+
+    for (i, seg) in pattern.iter().enumerate() {
+        if *seg == "*" && i == pattern.len() - 1 {
+            return true;
+        }
+        match label.get(i) {
+            Some(l) if *seg == "*" || seg == l => continue,
+            _ => return false,
+        }
+    }
+    label.len() == pattern.len()
+}
@@ -5,7 +5,9 @@ use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 const MAX_LOGIN_TRIES: usize = 3;
 
@@ -38,7 +40,111 @@ pub const ADMIN_ID: UserId = UserId(0);
 
 pub type Username = String;
 
+/// Identifies a [`Group`] within a [`GroupDb`], the same way [`UserId`] identifies a [`User`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GroupId(u64);
+
+impl GroupId {
+    pub fn new(num: u64) -> Self {
+        Self(num)
+    }
+
+    pub fn tick_next(&mut self) -> Self {
+        let this = *self;
+        self.0 += 1;
+        this
+    }
+}
+
+/// Every node is owned by this group until reassigned, much like `root` owns a freshly
+/// created file on a Unix system before a `chown`/`chgrp`.
+pub const ROOT_GID: GroupId = GroupId(0);
+
+pub type Groupname = String;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Group {
+    id: GroupId,
+    name: Groupname,
+    members: std::collections::HashSet<UserId>,
+}
+
+impl Group {
+    fn new(id: GroupId, name: Groupname) -> Self {
+        Self {
+            id,
+            name,
+            members: Default::default(),
+        }
+    }
+}
+
+/// Group database, kept next to [`UserDb`] the way `/etc/group` is kept next to `/etc/passwd`.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct GroupDb {
+    id_ctr: GroupId,
+    gnames: HashMap<Groupname, GroupId>,
+    groups: HashMap<GroupId, Group>,
+}
+
+impl Default for GroupDb {
+    fn default() -> Self {
+        let mut this = Self {
+            id_ctr: GroupId::default(),
+            gnames: HashMap::new(),
+            groups: HashMap::new(),
+        };
+        let id = this.id_ctr.tick_next();
+        this.gnames.insert("root".to_string(), id);
+        this.groups.insert(id, Group::new(id, "root".to_string()));
+        this
+    }
+}
+
+impl GroupDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_group(&mut self, name: &str) -> anyhow::Result<GroupId> {
+        if self.gnames.contains_key(name) {
+            anyhow::bail!("group exists")
+        }
+        let id = self.id_ctr.tick_next();
+        self.gnames.insert(name.to_string(), id);
+        self.groups.insert(id, Group::new(id, name.to_string()));
+        Ok(id)
+    }
+
+    pub fn id_of(&self, name: &str) -> anyhow::Result<GroupId> {
+        self.gnames
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("group not found"))
+    }
+
+    pub fn add_member(&mut self, gid: GroupId, uid: UserId) -> anyhow::Result<()> {
+        let group = self
+            .groups
+            .get_mut(&gid)
+            .ok_or_else(|| anyhow!("group not found"))?;
+        group.members.insert(uid);
+        Ok(())
+    }
+
+    /// Whether `uid` belongs to the group `gid`. Admin is implicitly a member of every group.
+    pub fn is_member(&self, gid: GroupId, uid: UserId) -> bool {
+        if uid == ADMIN_ID {
+            return true;
+        }
+        self.groups
+            .get(&gid)
+            .map(|group| group.members.contains(&uid))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Op {
     Read,
     Write,
@@ -46,36 +152,125 @@ pub enum Op {
     Control,
 }
 
-impl<'a> From<&'a [Op]> for Perms {
-    fn from(ops: &'a [Op]) -> Self {
-        let mut this = Self::default();
-        for op in ops {
-            match op {
-                Op::Read => this.read = true,
-                Op::Write => this.write = true,
-                Op::Exec => this.exec = true,
-                Op::Control => this.control = true,
-            }
+/// The outcome of checking a single [`Op`] against a subject's [`Mode`]. `Prompt` defers the
+/// decision to a `PermissionPrompter` rather than granting or denying outright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Policy {
+    Granted,
+    Denied,
+    Prompt,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Denied
+    }
+}
+
+impl Policy {
+    /// Combines the policies of several ops into one: denied wins over prompt, which wins
+    /// over granted, so a mixed request is never more permissive than its strictest op.
+    fn combine(self, other: Self) -> Self {
+        use Policy::*;
+        match (self, other) {
+            (Denied, _) | (_, Denied) => Denied,
+            (Prompt, _) | (_, Prompt) => Prompt,
+            (Granted, Granted) => Granted,
         }
-        this
     }
 }
 
+/// A single `rwxc` permission set, as granted to one of a node's owner/group/other subjects.
+/// Each bit is tri-state: granted outright, denied outright, or deferred to a prompt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mode {
+    pub read: Policy,
+    pub write: Policy,
+    pub exec: Policy,
+    pub control: Policy,
+}
+
+impl Mode {
+    pub fn policy_for(&self, op: &Op) -> Policy {
+        match op {
+            Op::Read => self.read,
+            Op::Write => self.write,
+            Op::Exec => self.exec,
+            Op::Control => self.control,
+        }
+    }
+
+    pub fn set_policy(&mut self, op: &Op, policy: Policy) {
+        let field = match op {
+            Op::Read => &mut self.read,
+            Op::Write => &mut self.write,
+            Op::Exec => &mut self.exec,
+            Op::Control => &mut self.control,
+        };
+        *field = policy;
+    }
+
+    /// The combined policy of requesting all of `ops` at once.
+    pub fn resolve(&self, ops: &[Op]) -> Policy {
+        ops.iter()
+            .map(|op| self.policy_for(op))
+            .reduce(Policy::combine)
+            .unwrap_or(Policy::Denied)
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sym = |policy: Policy, granted: char| match policy {
+            Policy::Granted => granted,
+            Policy::Denied => '-',
+            Policy::Prompt => '?',
+        };
+        write!(
+            f,
+            "{}{}{}{}",
+            sym(self.read, 'r'),
+            sym(self.write, 'w'),
+            sym(self.exec, 'x'),
+            sym(self.control, 'c')
+        )
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().collect();
+        if chars.len() != 4 {
+            anyhow::bail!("expected a 4-character rwxc mode, got {:?}", s);
+        }
+        let policy_of = |c: char, granted: char| match c {
+            c if c == granted => Ok(Policy::Granted),
+            '-' => Ok(Policy::Denied),
+            '?' => Ok(Policy::Prompt),
+            _ => anyhow::bail!("unexpected character: {:?}", c),
+        };
+        Ok(Self {
+            read: policy_of(chars[0], 'r')?,
+            write: policy_of(chars[1], 'w')?,
+            exec: policy_of(chars[2], 'x')?,
+            control: policy_of(chars[3], 'c')?,
+        })
+    }
+}
+
+/// The full owner/group/other permission triple of a [`Node`], e.g. `rwxc r--- ----`.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Perms {
-    pub read: bool,
-    pub write: bool,
-    pub exec: bool,
-    pub control: bool,
+    pub owner: Mode,
+    pub group: Mode,
+    pub other: Mode,
 }
 
 impl std::fmt::Display for Perms {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let read = if self.read { 'r' } else { '-' };
-        let write = if self.write { 'w' } else { '-' };
-        let exec = if self.exec { 'x' } else { '-' };
-        let control = if self.control { 'c' } else { '-' };
-        write!(f, "{}{}{}{}", read, write, exec, control)
+        write!(f, "{} {} {}", self.owner, self.group, self.other)
     }
 }
 
@@ -83,17 +278,24 @@ impl FromStr for Perms {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut this = Self::default();
-        for c in s.chars() {
-            match c {
-                'r' => this.read = true,
-                'w' => this.write = true,
-                'e' => this.exec = true,
-                'c' => this.control = true,
-                _ => anyhow::bail!("unexpected character: {:?}", c),
-            }
+        let mut parts = s.split_whitespace();
+        let mut next = || {
+            parts
+                .next()
+                .ok_or_else(|| anyhow!("expected \"rwxc rwxc rwxc\""))
+                .and_then(Mode::from_str)
+        };
+        let owner = next()?;
+        let group = next()?;
+        let other = next()?;
+        if parts.next().is_some() {
+            anyhow::bail!("expected exactly three rwxc groups");
         }
-        Ok(this)
+        Ok(Self {
+            owner,
+            group,
+            other,
+        })
     }
 }
 
@@ -102,6 +304,13 @@ pub struct User {
     id: UserId,
     name: Username,
     pass: String,
+    /// `Argon2(password, salt = SHA-256(username))`, kept only so a CRAM-style
+    /// challenge-response login can be verified without ever putting the password on the
+    /// wire. The salt is derived from the username rather than drawn at random so a CRAM
+    /// client can recompute the same verifier from credentials alone, but it still means
+    /// two accounts never share a verifier and a stolen database can't be cracked with a
+    /// single rainbow table the way a bare unsalted digest could.
+    cram_verifier: [u8; 32],
     last_login_tries: usize,
 }
 
@@ -111,6 +320,7 @@ impl User {
             id,
             name,
             pass: Default::default(),
+            cram_verifier: [0; 32],
             last_login_tries: 0,
         };
         this.change_pass(pass)?;
@@ -142,6 +352,28 @@ impl User {
         }
     }
 
+    /// Verifies a CRAM-style response: `response` should be `HMAC-SHA256(cram_verifier,
+    /// nonce)`, which only someone who knows the password (and can thus recompute
+    /// `cram_verifier`) could have produced. Shares the same blocked-account accounting as
+    /// [`Self::verify_pass`].
+    fn verify_cram(&mut self, nonce: &[u8], response: &[u8]) -> anyhow::Result<()> {
+        if self.is_blocked() {
+            anyhow::bail!("account if blocked")
+        }
+
+        let mut mac = HmacSha256::new_from_slice(&self.cram_verifier)
+            .expect("HMAC accepts a key of any length");
+        mac.update(nonce);
+
+        if mac.verify_slice(response).is_ok() {
+            self.reset_login_tries();
+            Ok(())
+        } else {
+            self.inc_login_tries()?;
+            Err(wrong_uname())
+        }
+    }
+
     fn inc_login_tries(&mut self) -> anyhow::Result<()> {
         self.last_login_tries += 1;
         if self.last_login_tries >= MAX_LOGIN_TRIES {
@@ -160,60 +392,32 @@ impl User {
 
         let argon2 = Argon2::default();
 
-        let pass = argon2
+        let hash = argon2
             .hash_password(pass.as_bytes(), &salt)
             .map_err(|err| anyhow!("deriving hash: {}", err))?
             .to_string();
 
-        self.pass = pass;
+        self.pass = hash;
+        self.cram_verifier = cram_verifier(&self.name, pass)?;
         Ok(())
     }
 }
 
-impl Perms {
-    pub fn intersects(&self, ops: &[Op]) -> bool {
-        ops.iter()
-            .map(|op| match op {
-                Op::Read => self.read,
-                Op::Write => self.write,
-                Op::Exec => self.exec,
-                Op::Control => self.control,
-            })
-            .reduce(|a, b| a && b)
-            .unwrap_or(false)
-    }
-}
-
-#[derive(Default, Debug, Serialize, Deserialize)]
-pub struct AccessMap {
-    perms: HashMap<UserId, Perms>,
+/// Derives a CRAM verifier as `Argon2(password, salt = SHA-256(username))`: salted per
+/// account, but deterministic from credentials alone so a CRAM client can recompute it
+/// without the server ever sharing the salt out of band. `pub` so CRAM clients (e.g.
+/// `bin/client.rs`) can derive the same verifier instead of re-deriving the HMAC key
+/// inline and drifting from the server's own derivation.
+pub fn cram_verifier(name: &str, pass: &str) -> anyhow::Result<[u8; 32]> {
+    let salt: [u8; 32] = Sha256::digest(name.as_bytes()).into();
+    let mut verifier = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(pass.as_bytes(), &salt, &mut verifier)
+        .map_err(|err| anyhow!("deriving cram verifier: {}", err))?;
+    Ok(verifier)
 }
 
-impl AccessMap {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn allows(&self, uid: UserId, ops: &[Op]) -> bool {
-        if uid == ADMIN_ID {
-            true
-        } else if let Some(perms) = self.perms.get(&uid) {
-            perms.intersects(ops)
-        } else {
-            false
-        }
-    }
-
-    pub fn set(&mut self, uid: UserId, perms: impl Into<Perms>) {
-        if uid != ADMIN_ID {
-            self.perms.insert(uid, perms.into());
-        }
-    }
-
-    pub fn get(&self, uid: UserId) -> Perms {
-        self.perms.get(&uid).copied().unwrap_or_default()
-    }
-}
+type HmacSha256 = Hmac<Sha256>;
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UserDb {
@@ -256,6 +460,21 @@ impl UserDb {
         user.verify_pass(pass)
     }
 
+    /// Resolves `username` to the id and CRAM verifier a challenge-response login needs,
+    /// without consuming a failed-attempt strike the way an actual credential check would.
+    pub fn cram_lookup(&self, username: &str) -> anyhow::Result<(UserId, [u8; 32])> {
+        let uid = self.id_of(username)?;
+        let user = self.users.get(&uid).expect("id_of only returns known ids");
+        Ok((uid, user.cram_verifier))
+    }
+
+    /// Verifies a CRAM response against `uid`'s stored verifier, applying the same
+    /// failed-attempt/blocking accounting as [`Self::login_with_id`].
+    pub fn verify_cram(&mut self, uid: UserId, nonce: &[u8], response: &[u8]) -> anyhow::Result<()> {
+        let user = self.users.get_mut(&uid).ok_or_else(wrong_uname)?;
+        user.verify_cram(nonce, response)
+    }
+
     pub fn change_pass(
         &mut self,
         uid: UserId,